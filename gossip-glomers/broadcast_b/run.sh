@@ -4,4 +4,4 @@ set -e
 
 cargo build --bin broadcast_b
 
-maelstrom test -w broadcast --bin "target/debug/broadcast_b" --node-count 5 --time-limit 20 --rate 10
+maelstrom test -w broadcast --bin "../target/debug/broadcast_b" --node-count 5 --time-limit 20 --rate 10
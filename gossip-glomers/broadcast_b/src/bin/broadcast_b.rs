@@ -1,211 +1,111 @@
-use anyhow::{Context, Result};
-use flyio::{parse_message, send_message, take_init};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::io::{self};
-
-#[derive(Deserialize, Serialize, Debug)]
-struct Broadcast {
-    msg_id: usize,
-    message: i32,
-}
-
-#[derive(Deserialize, Serialize, Debug)]
-struct BroadcastOK {
-    msg_id: usize,
-    in_reply_to: usize,
-}
-
-#[derive(Deserialize, Serialize, Debug)]
-struct Read {
-    msg_id: usize,
+use anyhow::Result;
+use flyio::topology::Grid;
+use flyio::{Body, Handler, Message, Node, Topology};
+use serde::de::IgnoredAny;
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+#[derive(Body, Debug)]
+#[body(replies = BodyOut<'a>)]
+enum BodyIn {
+    #[reply]
+    Broadcast {
+        message: i32,
+    },
+    #[reply(messages: &'a BTreeSet<i32>)]
+    Read,
+    #[reply]
+    Topology {
+        topology: Grid,
+    },
+    #[reply]
+    Gossip {
+        messages: Vec<i32>,
+    },
+    GossipOK(IgnoredAny),
 }
 
 #[derive(Serialize, Debug)]
-struct ReadOK<'a> {
-    msg_id: usize,
-    in_reply_to: usize,
-    messages: &'a Vec<i32>,
-}
-
-#[derive(Deserialize, Serialize, Debug)]
-struct Topology {
-    msg_id: usize,
-    topology: HashMap<String, Vec<String>>,
-}
-
-#[derive(Deserialize, Serialize, Debug)]
-struct TopologyOK {
-    msg_id: usize,
-    in_reply_to: usize,
-}
-
-#[derive(Deserialize, Debug)]
-struct GossipIn<'a> {
-    msg_id: usize,
-    messages: Vec<i32>,
-    #[serde(borrow)]
-    nodes: Vec<&'a str>,
-}
-
-#[derive(Serialize, Debug)]
-struct GossipOut<'a, T> {
+#[serde(tag = "type", rename = "gossip")]
+struct GossipOut<'a> {
     msg_id: usize,
     messages: &'a [i32],
-    nodes: &'a [T],
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-struct GossipOK {
-    msg_id: usize,
-    in_reply_to: usize,
+struct Broadcaster {
+    seen: BTreeSet<i32>,
+    topology: Topology,
+    /// The map from the last `topology` message.
+    grid: Grid,
+    neighbours: Vec<String>,
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(tag = "type")]
-enum BodyIn<'a> {
-    #[serde(rename = "broadcast")]
-    Broadcast(Broadcast),
-    #[serde(rename = "read")]
-    Read(Read),
-    #[serde(rename = "topology")]
-    Topology(Topology),
-    #[serde(rename = "gossip")]
-    #[serde(borrow)]
-    Gossip(GossipIn<'a>),
-    #[serde(rename = "gossip_ok")]
-    GossipOK(GossipOK),
-}
+impl Broadcaster {
+    /// Sends `messages` to every neighbour but `except`, which they came from.
+    fn gossip(&self, node: &mut Node, except: Option<&str>, messages: &[i32]) -> Result<()> {
+        for dest in &self.neighbours {
+            if Some(dest.as_str()) == except {
+                continue;
+            }
+            let msg_id = node.next_message_id()?;
+            node.send(dest, GossipOut { msg_id, messages })?;
+        }
 
-#[derive(Serialize, Debug)]
-#[serde(tag = "type")]
-enum BodyOut<'a> {
-    #[serde(rename = "broadcast_ok")]
-    BroadcastOK(BroadcastOK),
-    #[serde(rename = "read_ok")]
-    ReadOK(ReadOK<'a>),
-    #[serde(rename = "topology_ok")]
-    TopologyOK(TopologyOK),
-    #[serde(rename = "gossip")]
-    Gossip(GossipOut<'a, String>),
-    #[serde(rename = "gossip")]
-    GossipRef(GossipOut<'a, &'a str>),
-    #[serde(rename = "gossip_ok")]
-    GossipOK(GossipOK),
+        Ok(())
+    }
 }
 
-pub fn main() -> Result<()> {
-    let mut lines = io::stdin().lines();
-    let mut stdout = io::stdout().lock();
-
-    let node = take_init(&mut lines, &mut stdout)?;
-
-    let mut message_id: usize = 0;
-    let mut next_message_id = move || {
-        message_id += 1;
-        message_id
-    };
-
-    let mut seen = Vec::<i32>::with_capacity(100);
-
-    for line in lines {
-        let line = line.context("reading message")?;
-        // dbg!(&line);
-        let message = parse_message::<BodyIn>(&line)?;
-
-        match message.body {
-            BodyIn::Broadcast(body) => {
-                seen.push(body.message);
-
-                send_message(
-                    &mut stdout,
-                    &node.id,
-                    message.src,
-                    BodyOut::BroadcastOK(BroadcastOK {
-                        msg_id: next_message_id(),
-                        in_reply_to: body.msg_id,
-                    }),
-                )?;
-
-                let mut gossip_to = |group: &[String]| -> Result<()> {
-                    if let Some((head, tail)) = group.split_first() {
-                        send_message(
-                            &mut stdout,
-                            &node.id,
-                            head,
-                            BodyOut::Gossip(GossipOut {
-                                msg_id: next_message_id(),
-                                messages: &[body.message],
-                                nodes: tail,
-                            }),
-                        )?;
-                    }
+impl Handler for Broadcaster {
+    type Body = BodyIn;
 
-                    Ok(())
-                };
+    fn handle(&mut self, node: &mut Node, message: Message<BodyIn>) -> Result<()> {
+        match &message.body {
+            BodyIn::Broadcast { message: value } => {
+                let new = self.seen.insert(*value);
+                node.reply(&message, BodyOut::broadcast_ok())?;
 
-                let nodes = node.node_ids.as_slice();
-                if nodes.len() >= 1 {
-                    let (a, b) = nodes.split_at(nodes.len() / 2);
-                    gossip_to(a)?;
-                    gossip_to(b)?;
+                if new {
+                    self.gossip(node, None, &[*value])?;
                 }
             }
-            BodyIn::Read(body) => {
-                let outgoing = BodyOut::ReadOK(ReadOK {
-                    msg_id: next_message_id(),
-                    in_reply_to: body.msg_id,
-                    messages: &seen,
-                });
-
-                send_message(&mut stdout, &node.id, message.src, outgoing)?;
-            }
-            BodyIn::Topology(body) => {
-                let outgoing = BodyOut::TopologyOK(TopologyOK {
-                    msg_id: next_message_id(),
-                    in_reply_to: body.msg_id,
-                });
-
-                send_message(&mut stdout, &node.id, message.src, outgoing)?;
+            BodyIn::Read => node.reply(&message, BodyOut::read_ok(&self.seen))?,
+            BodyIn::Topology { topology } => {
+                self.grid = topology.clone();
+                self.neighbours = self
+                    .topology
+                    .neighbours(&node.id, &node.node_ids, &self.grid);
+
+                node.reply(&message, BodyOut::topology_ok())?;
             }
-            BodyIn::Gossip(body) => {
-                seen.extend_from_slice(body.messages.as_slice());
-
-                send_message(
-                    &mut stdout,
-                    &node.id,
-                    message.src,
-                    BodyOut::GossipOK(GossipOK {
-                        msg_id: next_message_id(),
-                        in_reply_to: body.msg_id,
-                    }),
-                )?;
-
-                let mut gossip_to = |group: &[&str]| -> Result<()> {
-                    if let Some((head, tail)) = group.split_first() {
-                        send_message(
-                            &mut stdout,
-                            &node.id,
-                            head,
-                            BodyOut::GossipRef(GossipOut {
-                                msg_id: next_message_id(),
-                                messages: body.messages.as_slice(),
-                                nodes: tail,
-                            }),
-                        )?;
-                    }
-
-                    Ok(())
-                };
-
-                let nodes = body.nodes.as_slice();
-                let (a, b) = nodes.split_at(nodes.len() / 2);
-                gossip_to(a)?;
-                gossip_to(b)?;
+            BodyIn::Gossip { messages } => {
+                let fresh: Vec<i32> = messages
+                    .iter()
+                    .copied()
+                    .filter(|value| self.seen.insert(*value))
+                    .collect();
+
+                node.reply(&message, BodyOut::gossip_ok())?;
+
+                // only what was new here, the rest went on when it first
+                // arrived
+                if !fresh.is_empty() {
+                    self.gossip(node, Some(&message.src), &fresh)?;
+                }
             }
             BodyIn::GossipOK(_) => {}
         }
+
+        Ok(())
     }
+}
 
-    Ok(())
+pub fn main() -> Result<()> {
+    let topology = Topology::from_env()?;
+
+    Node::run(|node| Broadcaster {
+        seen: BTreeSet::new(),
+        topology,
+        grid: Grid::new(),
+        neighbours: topology.neighbours(&node.id, &node.node_ids, &Grid::new()),
+    })
 }
@@ -1,274 +1,115 @@
-use anyhow::{anyhow, Context, Result};
-use flyio::{parse_message, send_message, take_init, Message, NodeInit};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::collections::HashSet;
-use std::io::StdoutLock;
-use std::io::{self};
-use std::mem;
-use std::sync::mpsc::{self};
-use std::sync::{atomic, Arc};
-use std::thread;
+use anyhow::Result;
+use flyio::gossip::{GossipIn, GossipStrategy, Metrics, Strategy};
+use flyio::topology::Grid;
+use flyio::{Body, FlatValues, Handler, Message, Node, Timer, Topology, ValueSet};
+use serde::de::IgnoredAny;
+use std::env;
+use std::sync::Arc;
 use std::time::Duration;
 
-#[derive(Deserialize, Serialize, Debug)]
-struct Broadcast {
-    msg_id: usize,
-    message: i32,
-}
-
-#[derive(Deserialize, Serialize, Debug)]
-struct BroadcastOK {
-    msg_id: usize,
-    in_reply_to: usize,
-}
-
-#[derive(Deserialize, Serialize, Debug)]
-struct Read {
-    msg_id: usize,
-}
-
-#[derive(Serialize, Debug)]
-struct ReadOK<'a> {
-    msg_id: usize,
-    in_reply_to: usize,
-    messages: &'a Vec<i32>,
-}
-
-#[derive(Deserialize, Serialize, Debug)]
-struct Topology {
-    msg_id: usize,
-    topology: HashMap<String, Vec<String>>,
-}
-
-#[derive(Deserialize, Serialize, Debug)]
-struct TopologyOK {
-    msg_id: usize,
-    in_reply_to: usize,
-}
-
-#[derive(Deserialize, Debug)]
-struct GossipIn {
-    // msg_id: usize,
-    messages: Vec<i32>,
-    nodes: Vec<String>,
-}
+const GOSSIP: &str = "gossip";
 
-#[derive(Serialize, Debug)]
-struct GossipOut<'a, T> {
-    msg_id: usize,
-    messages: &'a [i32],
-    nodes: &'a [T],
-}
-
-#[derive(Deserialize, Serialize, Debug)]
-struct GossipOK {
-    msg_id: usize,
-    in_reply_to: usize,
-}
-
-#[derive(Deserialize, Debug)]
-struct Tick;
-
-#[derive(Deserialize, Debug)]
-#[serde(tag = "type")]
+#[derive(Body, Debug)]
+#[body(replies = BodyOut<'a>)]
 enum BodyIn {
-    #[serde(rename = "broadcast")]
-    Broadcast(Broadcast),
-    #[serde(rename = "read")]
-    Read(Read),
-    #[serde(rename = "topology")]
-    Topology(Topology),
-    #[serde(rename = "gossip")]
+    #[reply]
+    Broadcast {
+        message: i32,
+    },
+    #[reply(messages: FlatValues<'a>)]
+    Read,
+    #[reply]
+    Topology {
+        topology: Grid,
+    },
+    #[reply]
     Gossip(GossipIn),
-    Tick(Tick),
-}
-
-#[derive(Serialize, Debug)]
-#[serde(tag = "type")]
-enum BodyOut<'a> {
-    #[serde(rename = "broadcast_ok")]
-    BroadcastOK(BroadcastOK),
-    #[serde(rename = "read_ok")]
-    ReadOK(ReadOK<'a>),
-    #[serde(rename = "topology_ok")]
-    TopologyOK(TopologyOK),
-    #[serde(rename = "gossip")]
-    Gossip(GossipOut<'a, String>),
+    // acks to a push the retrier has since resent under a new msg_id
+    GossipOK(IgnoredAny),
 }
 
-struct Node<'a> {
-    message_id: usize,
-    init: NodeInit,
-    my: HashSet<i32>,
-    theirs: HashSet<i32>,
-    lines: mpsc::Receiver<Result<String, std::io::Error>>,
-    stdout: StdoutLock<'a>,
+struct Broadcaster {
+    my: ValueSet,
+    theirs: ValueSet,
+    gossip: Box<dyn GossipStrategy + Send>,
+    topology: Topology,
 }
 
-impl<'a> Node<'a> {
-    fn next_message_id(&mut self) -> usize {
-        self.message_id += 1;
-        self.message_id
-    }
-
-    fn send_message<'b, T>(&mut self, dest: &'b str, body: T) -> Result<()>
-    where
-        T: Serialize,
-    {
-        send_message(&mut self.stdout, &self.init.id, dest, body).context("sending message")
-    }
-
-    fn gossip_to(&mut self, group: &[String], messages: &[i32]) -> Result<()> {
-        let Some((dest, tail)) = group.split_first() else {
-            return Ok(());
-        };
-
-        let msg_id = self.next_message_id();
-
-        self.send_message(
-            dest,
-            BodyOut::Gossip(GossipOut {
-                msg_id,
-                messages,
-                nodes: tail,
-            }),
-        )?;
+impl Handler for Broadcaster {
+    type Body = BodyIn;
 
+    fn start(&mut self, node: &mut Node) -> Result<()> {
+        let every = Timer::every(Duration::from_millis(250)).with_jitter(Duration::from_millis(25));
+        node.set_timer(GOSSIP, every);
         Ok(())
     }
 
-    fn next(&mut self) -> Option<Result<Message<BodyIn>>> {
-        let Ok(line) = self.lines.recv() else {
-            return None;
-        };
-
-        match line {
-            Err(err) => Some(Err(anyhow!(err))),
-            Ok(line) => {
-                if line == "tick" {
-                    Some(Ok(Message {
-                        src: "self".into(),
-                        dest: "self".into(),
-                        body: BodyIn::Tick(Tick),
-                    }))
-                } else {
-                    Some(parse_message(&line))
+    fn handle(&mut self, node: &mut Node, message: Message<BodyIn>) -> Result<()> {
+        match message.body {
+            BodyIn::Broadcast { message: value } => {
+                if self.my.insert(value) {
+                    self.gossip.broadcast(node, value)?;
                 }
-            }
-        }
-    }
 
-    fn main(&mut self) -> Result<()> {
-        loop {
-            let Some(message) = self.next() else {
-                break;
-            };
-            let Ok(message) = message else {
-                if let Err(err) = message {
-                    eprintln!("Application error: {err}");
-                };
-                continue;
-            };
-
-            match message.body {
-                BodyIn::Broadcast(body) => {
-                    self.my.insert(body.message);
-
-                    let message_id = self.next_message_id();
-                    self.send_message(
-                        &message.src,
-                        BodyOut::BroadcastOK(BroadcastOK {
-                            msg_id: message_id,
-                            in_reply_to: body.msg_id,
-                        }),
-                    )?;
-                }
-                BodyIn::Read(body) => {
-                    let mut seen = Vec::new();
-                    seen.extend(self.my.clone().into_iter());
-                    seen.extend(self.theirs.clone().into_iter());
-                    let outgoing = BodyOut::ReadOK(ReadOK {
-                        msg_id: self.next_message_id(),
-                        in_reply_to: body.msg_id,
-                        messages: &seen,
-                    });
-
-                    self.send_message(&message.src, outgoing)?;
-                }
-                BodyIn::Topology(body) => {
-                    let outgoing = BodyOut::TopologyOK(TopologyOK {
-                        msg_id: self.next_message_id(),
-                        in_reply_to: body.msg_id,
-                    });
+                node.reply(&message, BodyOut::broadcast_ok())?;
+            }
+            BodyIn::Read => {
+                let seen = self.my.union(&self.theirs);
+                node.reply(&message, BodyOut::read_ok(seen.flat()))?;
+            }
+            BodyIn::Topology { ref topology } => {
+                let neighbours = self.topology.neighbours(&node.id, &node.node_ids, topology);
+                self.gossip.neighbours(neighbours);
 
-                    self.send_message(&message.src, outgoing)?;
+                node.reply(&message, BodyOut::topology_ok())?;
+            }
+            BodyIn::Gossip(body) => {
+                if let Some(in_reply_to) = body.msg_id {
+                    node.reply_ok(&message.src, in_reply_to, BodyOut::gossip_ok())?;
                 }
-                BodyIn::Gossip(body) => {
-                    self.theirs.extend(&body.messages);
 
-                    let nodes = body.nodes.as_slice();
-                    let (a, b) = nodes.split_at(nodes.len() / 2);
-                    self.gossip_to(a, body.messages.as_slice())?;
-                    self.gossip_to(b, body.messages.as_slice())?;
-                }
-                BodyIn::Tick(_) => {
-                    // TODO
-                    let node_ids = mem::take(&mut self.init.node_ids);
-                    let seen_vec: Vec<_> = self.my.clone().into_iter().collect();
-                    let nodes = node_ids.as_slice();
-                    if nodes.len() >= 1 {
-                        let (a, b) = nodes.split_at(nodes.len() / 2);
-                        self.gossip_to(a, &seen_vec)?;
-                        self.gossip_to(b, &seen_vec)?;
-                    }
-                    self.init.node_ids = node_ids;
-                }
+                let seen = self.my.union(&self.theirs);
+                let messages = self
+                    .gossip
+                    .receive(node, &message.src, body.gossip, &seen)?;
+                self.theirs.union_with(&messages);
             }
+            BodyIn::GossipOK(_) => {}
         }
 
         Ok(())
     }
+
+    fn timer(&mut self, node: &mut Node, _name: &str) -> Result<()> {
+        let seen = self.my.union(&self.theirs);
+        self.gossip.tick(node, &seen)
+    }
 }
 
 pub fn main() -> Result<()> {
-    let (send, lines) = mpsc::channel();
-
-    let timer_send = send.clone();
-    let timer_on = Arc::new(atomic::AtomicBool::new(true));
-    let timer_on_clone = timer_on.clone();
-    let timer = thread::spawn(move || {
-        while timer_on_clone.load(atomic::Ordering::Relaxed) {
-            thread::sleep(Duration::from_millis(250));
-            timer_send.send(Ok("tick".into())).unwrap();
-        }
-    });
-
-    let reader = thread::spawn(move || {
-        for line in io::stdin().lines() {
-            send.send(line).unwrap();
-        }
-        timer_on.store(false, atomic::Ordering::Relaxed);
-    });
-
-    let mut stdout = io::stdout().lock();
-
-    let node_init = take_init(&lines, &mut stdout)?;
-
-    let mut node = Node {
-        message_id: 0,
-        init: node_init,
-        my: HashSet::with_capacity(256),
-        theirs: HashSet::with_capacity(256),
-        lines,
-        stdout,
+    let strategy = Strategy::from_env()?;
+    let topology = Topology::from_env()?;
+    let metrics = Arc::new(Metrics::default());
+
+    let make_handler = |node: &Node| Broadcaster {
+        my: ValueSet::new(),
+        theirs: ValueSet::new(),
+        gossip: strategy.build(
+            node,
+            topology.neighbours(&node.id, &node.node_ids, &Grid::new()),
+            metrics.clone(),
+        ),
+        topology,
     };
 
-    node.main()?;
-
-    timer.join().unwrap();
-    // TODO: fix anyhow
-    reader.join().unwrap();
+    let result = match env::args().nth(1).as_deref() {
+        #[cfg(feature = "simulate")]
+        Some("simulate") => harness::cli::simulate(env::args().skip(2), make_handler),
+        #[cfg(not(feature = "simulate"))]
+        Some("simulate") => Err(anyhow::anyhow!("built without the simulate feature")),
+        _ => Node::run(make_handler),
+    };
 
-    Ok(())
+    eprintln!("gossip {strategy}: {metrics}");
+    result
 }
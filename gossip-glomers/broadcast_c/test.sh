@@ -7,5 +7,7 @@ set -e
   echo '{"src":"p1", "dest": "n1", "body":{"type": "broadcast", "msg_id": 2, "message": 1000}}'
   echo '{"src":"p1", "dest": "n1", "body":{"type": "read", "msg_id": 3}}'
   echo '{"src":"p1", "dest": "n1", "body":{"type": "read", "msg_id": 4}}'
-  echo '{"src":"n2", "dest": "n1", "body":{"type": "gossip", "msg_id": 2, "messages": [2000, 3000], "nodes": ["n2","n3","n4"]}}'
-} | cargo run --bin broadcast_c
+  echo '{"src":"p1", "dest": "n1", "body":{"type": "topology", "msg_id": 5, "topology": {"n1": ["n2", "n3"], "n2": ["n1"], "n3": ["n1"]}}}'
+  echo '{"src":"n2", "dest": "n1", "body":{"type": "gossip", "kind": "push", "msg_id": 2, "messages": [2000, 3000]}}'
+  sleep 1
+} | TOPOLOGY=grid cargo run --bin broadcast_c
@@ -0,0 +1,12 @@
+#!/usr/bin/env bash
+
+# Restarts a node every couple of seconds, the nodes keep their values in a
+# write-ahead log (see STATE_DIR) and must not lose any acknowledged one.
+
+set -e
+
+cargo build --bin broadcast_d
+cargo build -p harness --bin harness
+
+../target/debug/harness --bin ../target/debug/broadcast_d --workload broadcast --node-count 5 \
+  --time-limit 20 --rate 100 --latency 20 --jitter 10 --nemesis crash --nemesis-interval 2 "$@"
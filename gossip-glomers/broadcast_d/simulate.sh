@@ -0,0 +1,21 @@
+#!/usr/bin/env bash
+
+# Replays fixed seeds of a simulated cluster, in virtual time, as regression
+# tests: a failing seed is reported and can be replayed with the same flags.
+
+set -e
+
+cargo build --bin broadcast_d --features simulate
+
+for seed in 1 2 3 4 5; do
+  ../target/debug/broadcast_d simulate --workload broadcast --node-count 5 --time-limit 10 \
+    --rate 20 --latency 20 --jitter 20 --nemesis partition --seed "$seed"
+  ../target/debug/broadcast_d simulate --workload broadcast --node-count 25 --time-limit 10 \
+    --rate 100 --latency 100 --jitter 50 --drop 0.05 --nemesis partition --settle 20 --seed "$seed"
+done
+
+# Partitions that outlast the gossip retries, healed by digest sync.
+for seed in 1 2 3; do
+  ../target/debug/broadcast_d simulate --workload broadcast --node-count 5 --time-limit 20 \
+    --rate 20 --latency 20 --jitter 20 --nemesis partition --nemesis-interval 8 --settle 10 --seed "$seed"
+done
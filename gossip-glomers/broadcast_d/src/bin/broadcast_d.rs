@@ -1,274 +1,370 @@
-use anyhow::{anyhow, Context, Result};
-use flyio::{parse_message, send_message, take_init, Message, NodeInit};
+use anyhow::{Context, Result};
+use flyio::topology::Grid;
+use flyio::{
+    Digest, FlatValues, Handler, Message, Node, Outbox, Reply, Retrier, Router, Timer, Topology,
+    Unrouted, ValueSet,
+};
+use serde::de::IgnoredAny;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::HashMap;
-use std::collections::HashSet;
-use std::io::StdoutLock;
-use std::io::{self};
+use std::env;
 use std::mem;
-use std::sync::mpsc::{self};
-use std::sync::{atomic, Arc};
-use std::thread;
 use std::time::Duration;
 
-#[derive(Deserialize, Serialize, Debug)]
-struct Broadcast {
-    msg_id: usize,
-    message: i32,
-}
+const GOSSIP: &str = "gossip";
+const SYNC: &str = "sync";
+const FLUSH: &str = "flush";
 
-#[derive(Deserialize, Serialize, Debug)]
-struct BroadcastOK {
-    msg_id: usize,
-    in_reply_to: usize,
-}
+/// Values a gossip message is sent with at the latest.
+const BATCH_VALUES: u64 = 256;
+/// How long new values wait for more to share a gossip message with. Paid
+/// once per hop: with 25 nodes in a star and 100ms links a value is read
+/// everywhere after ~290ms (max ~390ms) at ~8.5 msgs per op, against 3d's
+/// 400ms/600ms/30. At 200ms the median was ~520ms.
+const BATCH_DELAY: Duration = Duration::from_millis(50);
 
-#[derive(Deserialize, Serialize, Debug)]
-struct Read {
-    msg_id: usize,
+#[derive(Deserialize, Debug)]
+struct Broadcast {
+    message: i32,
 }
 
 #[derive(Serialize, Debug)]
 struct ReadOK<'a> {
-    msg_id: usize,
-    in_reply_to: usize,
-    messages: &'a Vec<i32>,
+    messages: FlatValues<'a>,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-struct Topology {
-    msg_id: usize,
-    topology: HashMap<String, Vec<String>>,
+#[derive(Deserialize, Debug)]
+struct TopologyIn {
+    topology: Grid,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-struct TopologyOK {
-    msg_id: usize,
-    in_reply_to: usize,
+#[derive(Deserialize, Debug)]
+struct GossipIn {
+    messages: ValueSet,
+}
+
+/// Sent through a [`Retrier`] which fills in `msg_id` and resends it until the
+/// peer replies with `gossip_ok`.
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename = "gossip")]
+struct GossipOut {
+    messages: ValueSet,
 }
 
+/// Anti-entropy: the sender's digest, answered with the receiver's values in
+/// the buckets they disagree on.
 #[derive(Deserialize, Debug)]
-struct GossipIn {
-    // msg_id: usize,
-    messages: Vec<i32>,
-    nodes: Vec<String>,
+struct Sync {
+    digest: Digest,
 }
 
 #[derive(Serialize, Debug)]
-struct GossipOut<'a, T> {
+#[serde(tag = "type", rename = "sync")]
+struct SyncOut {
     msg_id: usize,
-    messages: &'a [i32],
-    nodes: &'a [T],
+    digest: Digest,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
-struct GossipOK {
-    msg_id: usize,
-    in_reply_to: usize,
+struct SyncOK {
+    buckets: Vec<i64>,
+    values: ValueSet,
 }
 
-#[derive(Deserialize, Debug)]
-struct Tick;
-
-#[derive(Deserialize, Debug)]
-#[serde(tag = "type")]
-enum BodyIn {
-    #[serde(rename = "broadcast")]
-    Broadcast(Broadcast),
-    #[serde(rename = "read")]
-    Read(Read),
-    #[serde(rename = "topology")]
-    Topology(Topology),
-    #[serde(rename = "gossip")]
-    Gossip(GossipIn),
-    Tick(Tick),
+/// What the node logs to get its values back after a restart.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum Record {
+    My(i32),
+    Theirs(ValueSet),
 }
 
-#[derive(Serialize, Debug)]
-#[serde(tag = "type")]
-enum BodyOut<'a> {
-    #[serde(rename = "broadcast_ok")]
-    BroadcastOK(BroadcastOK),
-    #[serde(rename = "read_ok")]
-    ReadOK(ReadOK<'a>),
-    #[serde(rename = "topology_ok")]
-    TopologyOK(TopologyOK),
-    #[serde(rename = "gossip")]
-    Gossip(GossipOut<'a, String>),
+#[derive(Deserialize, Serialize, Debug)]
+struct State {
+    my: ValueSet,
+    theirs: ValueSet,
 }
 
-struct Node<'a> {
-    message_id: usize,
-    init: NodeInit,
-    my: HashSet<i32>,
-    theirs: HashSet<i32>,
-    lines: mpsc::Receiver<Result<String, std::io::Error>>,
-    stdout: StdoutLock<'a>,
+struct Broadcaster {
+    topology: Topology,
+    /// The map from the last `topology` message.
+    grid: Grid,
+    neighbours: Vec<String>,
+    my: ValueSet,
+    theirs: ValueSet,
+    /// Values each neighbour is known to have: it either gossiped them to us
+    /// or acknowledged our gossip carrying them.
+    known: HashMap<String, ValueSet>,
+    gossip: Retrier<GossipOut>,
+    /// New values on their way to the neighbours.
+    outbox: Outbox,
+    /// Every other node, taken in turn for anti-entropy.
+    peers: Vec<String>,
+    next_sync: usize,
 }
 
-impl<'a> Node<'a> {
-    fn next_message_id(&mut self) -> usize {
-        self.message_id += 1;
-        self.message_id
-    }
+impl Broadcaster {
+    /// What `dest` is neither known to have nor about to receive.
+    fn delta(&self, dest: &str) -> ValueSet {
+        let mut in_flight = ValueSet::new();
+        for gossip in self.gossip.unacked(dest) {
+            in_flight.union_with(&gossip.messages);
+        }
 
-    fn send_message<'b, T>(&mut self, dest: &'b str, body: T) -> Result<()>
-    where
-        T: Serialize,
-    {
-        send_message(&mut self.stdout, &self.init.id, dest, body).context("sending message")
+        let delta = self.my.union(&self.theirs).difference(&in_flight);
+        match self.known.get(dest) {
+            Some(known) => delta.difference(known),
+            None => delta,
+        }
     }
 
-    fn gossip_to(&mut self, group: &[String], messages: &[i32]) -> Result<()> {
-        let Some((dest, tail)) = group.split_first() else {
-            return Ok(());
-        };
+    /// Queues `values` for every neighbour but `except` that is not known to
+    /// have them.
+    fn queue(&mut self, node: &mut Node, values: &ValueSet, except: Option<&str>) -> Result<()> {
+        let neighbours = mem::take(&mut self.neighbours);
+        for dest in &neighbours {
+            if Some(dest.as_str()) == except {
+                continue;
+            }
+            let values = match self.known.get(dest) {
+                Some(known) => values.difference(known),
+                None => values.clone(),
+            };
+            if let Some(messages) = self.outbox.add(dest, &values, node.now()) {
+                self.gossip.send(node, dest, GossipOut { messages })?;
+            }
+        }
+        self.neighbours = neighbours;
 
-        let msg_id = self.next_message_id();
+        self.schedule_flush(node);
+        Ok(())
+    }
 
-        self.send_message(
-            dest,
-            BodyOut::Gossip(GossipOut {
-                msg_id,
-                messages,
-                nodes: tail,
-            }),
-        )?;
+    fn flush(&mut self, node: &mut Node) -> Result<()> {
+        for (dest, messages) in self.outbox.due(node.now()) {
+            self.gossip.send(node, &dest, GossipOut { messages })?;
+        }
 
+        self.schedule_flush(node);
         Ok(())
     }
 
-    fn next(&mut self) -> Option<Result<Message<BodyIn>>> {
-        let Ok(line) = self.lines.recv() else {
-            return None;
-        };
+    /// Wakes [`Broadcaster::flush`] when the next batch is due.
+    fn schedule_flush(&self, node: &mut Node) {
+        if let Some(due) = self.outbox.next_due() {
+            let after = due.saturating_duration_since(node.now());
+            node.set_timer(FLUSH, Timer::once(after));
+        }
+    }
 
-        match line {
-            Err(err) => Some(Err(anyhow!(err))),
-            Ok(line) => {
-                if line == "tick" {
-                    Some(Ok(Message {
-                        src: "self".into(),
-                        dest: "self".into(),
-                        body: BodyIn::Tick(Tick),
-                    }))
-                } else {
-                    Some(parse_message(&line))
-                }
+    /// Resends unacknowledged gossip and queues whatever a neighbour still
+    /// lacks, e.g. values replayed from the log after a restart or seen
+    /// before a `topology` made it a neighbour.
+    fn gossip(&mut self, node: &mut Node) -> Result<()> {
+        for (dest, gossip) in self.gossip.poll(node)? {
+            self.known
+                .entry(dest)
+                .or_default()
+                .union_with(&gossip.messages);
+        }
+
+        let neighbours = mem::take(&mut self.neighbours);
+        for dest in &neighbours {
+            let messages = self.delta(dest);
+            if let Some(messages) = self.outbox.add(dest, &messages, node.now()) {
+                self.gossip.send(node, dest, GossipOut { messages })?;
             }
         }
+        self.neighbours = neighbours;
+
+        self.flush(node)
     }
 
-    fn main(&mut self) -> Result<()> {
-        loop {
-            let Some(message) = self.next() else {
-                break;
-            };
-            let Ok(message) = message else {
-                if let Err(err) = message {
-                    eprintln!("Application error: {err}");
-                };
-                continue;
-            };
+    /// Compares digests with the next peer, which catches up what gossip
+    /// misses, e.g. a batch the retrier dropped on an `error` reply.
+    fn sync(&mut self, node: &mut Node) -> Result<()> {
+        if self.peers.is_empty() {
+            return Ok(());
+        }
+        let dest = self.peers[self.next_sync % self.peers.len()].clone();
+        self.next_sync += 1;
 
-            match message.body {
-                BodyIn::Broadcast(body) => {
-                    self.my.insert(body.message);
-
-                    let message_id = self.next_message_id();
-                    self.send_message(
-                        &message.src,
-                        BodyOut::BroadcastOK(BroadcastOK {
-                            msg_id: message_id,
-                            in_reply_to: body.msg_id,
-                        }),
-                    )?;
-                }
-                BodyIn::Read(body) => {
-                    let mut seen = Vec::new();
-                    seen.extend(self.my.clone().into_iter());
-                    seen.extend(self.theirs.clone().into_iter());
-                    let outgoing = BodyOut::ReadOK(ReadOK {
-                        msg_id: self.next_message_id(),
-                        in_reply_to: body.msg_id,
-                        messages: &seen,
-                    });
-
-                    self.send_message(&message.src, outgoing)?;
-                }
-                BodyIn::Topology(body) => {
-                    let outgoing = BodyOut::TopologyOK(TopologyOK {
-                        msg_id: self.next_message_id(),
-                        in_reply_to: body.msg_id,
-                    });
-
-                    self.send_message(&message.src, outgoing)?;
-                }
-                BodyIn::Gossip(body) => {
-                    self.theirs.extend(&body.messages);
-
-                    let nodes = body.nodes.as_slice();
-                    let (a, b) = nodes.split_at(nodes.len() / 2);
-                    self.gossip_to(a, body.messages.as_slice())?;
-                    self.gossip_to(b, body.messages.as_slice())?;
-                }
-                BodyIn::Tick(_) => {
-                    // TODO
-                    let node_ids = mem::take(&mut self.init.node_ids);
-                    let seen_vec: Vec<_> = self.my.clone().into_iter().collect();
-                    let nodes = node_ids.as_slice();
-                    if nodes.len() >= 1 {
-                        let (a, b) = nodes.split_at(nodes.len() / 2);
-                        self.gossip_to(a, &seen_vec)?;
-                        self.gossip_to(b, &seen_vec)?;
-                    }
-                    self.init.node_ids = node_ids;
-                }
-            }
+        let outgoing = SyncOut {
+            msg_id: node.next_message_id()?,
+            digest: Digest::of(&self.my.union(&self.theirs)),
+        };
+        node.send(&dest, outgoing)
+    }
+
+    fn handle_broadcast(&mut self, body: Broadcast, reply: &mut Reply) -> Result<()> {
+        if self.my.insert(body.message) {
+            reply.node().log(&Record::My(body.message))?;
+            let value = ValueSet::from_iter([body.message]);
+            self.queue(reply.node(), &value, None)?;
         }
+        reply.ok(())
+    }
 
-        Ok(())
+    fn handle_read(&mut self, _: IgnoredAny, reply: &mut Reply) -> Result<()> {
+        let seen = self.my.union(&self.theirs);
+        reply.ok(ReadOK {
+            messages: seen.flat(),
+        })
     }
-}
 
-pub fn main() -> Result<()> {
-    let (send, lines) = mpsc::channel();
-
-    let timer_send = send.clone();
-    let timer_on = Arc::new(atomic::AtomicBool::new(true));
-    let timer_on_clone = timer_on.clone();
-    let timer = thread::spawn(move || {
-        while timer_on_clone.load(atomic::Ordering::Relaxed) {
-            thread::sleep(Duration::from_millis(750));
-            timer_send.send(Ok("tick".into())).unwrap();
+    fn handle_topology(&mut self, body: TopologyIn, reply: &mut Reply) -> Result<()> {
+        let node = reply.node();
+        self.grid = body.topology;
+        self.neighbours = self
+            .topology
+            .neighbours(&node.id, &node.node_ids, &self.grid);
+        reply.ok(())
+    }
+
+    fn handle_gossip(&mut self, body: GossipIn, reply: &mut Reply) -> Result<()> {
+        let src = reply.src();
+        let new = body.messages.difference(&self.my.union(&self.theirs));
+        if !new.is_empty() {
+            reply.node().log(&Record::Theirs(new.clone()))?;
         }
-    });
+        self.theirs.union_with(&body.messages);
+        self.known
+            .entry(src.to_string())
+            .or_default()
+            .union_with(&body.messages);
+
+        reply.ok(())?;
+        self.queue(reply.node(), &new, Some(src))
+    }
+
+    fn handle_sync(&mut self, body: Sync, reply: &mut Reply) -> Result<()> {
+        let seen = self.my.union(&self.theirs);
+        let buckets = Digest::of(&seen).differing(&body.digest);
+        reply.ok(SyncOK {
+            values: seen.in_buckets(&buckets),
+            buckets,
+        })
+    }
 
-    let reader = thread::spawn(move || {
-        for line in io::stdin().lines() {
-            send.send(line).unwrap();
+    fn handle_sync_ok(&mut self, body: SyncOK, reply: &mut Reply) -> Result<()> {
+        let src = reply.src();
+        let missing = self
+            .my
+            .union(&self.theirs)
+            .in_buckets(&body.buckets)
+            .difference(&body.values);
+
+        let new = body.values.difference(&self.my.union(&self.theirs));
+        if !new.is_empty() {
+            reply.node().log(&Record::Theirs(new))?;
         }
-        timer_on.store(false, atomic::Ordering::Relaxed);
-    });
+        self.theirs.union_with(&body.values);
+        let known = self.known.entry(src.to_string()).or_default();
+        known.union_with(&body.values);
+        let missing = missing.difference(known);
+        if !missing.is_empty() {
+            self.gossip
+                .send(reply.node(), src, GossipOut { messages: missing })?;
+        }
+        Ok(())
+    }
+}
+
+impl Handler for Broadcaster {
+    type Body = Unrouted;
 
-    let mut stdout = io::stdout().lock();
+    fn start(&mut self, node: &mut Node) -> Result<()> {
+        let every = Timer::every(Duration::from_millis(100)).with_jitter(Duration::from_millis(10));
+        node.set_timer(GOSSIP, every);
+        let every = Timer::every(Duration::from_secs(1)).with_jitter(Duration::from_millis(100));
+        node.set_timer(SYNC, every);
+        Ok(())
+    }
 
-    let node_init = take_init(&lines, &mut stdout)?;
+    fn handle(&mut self, _node: &mut Node, message: Message<Unrouted>) -> Result<()> {
+        match message.body {}
+    }
 
-    let mut node = Node {
-        message_id: 0,
-        init: node_init,
-        my: HashSet::with_capacity(256),
-        theirs: HashSet::with_capacity(256),
-        lines,
-        stdout,
-    };
+    fn snapshot(&self) -> Result<Option<Value>> {
+        let state = State {
+            my: self.my.clone(),
+            theirs: self.theirs.clone(),
+        };
+        Ok(Some(serde_json::to_value(state)?))
+    }
+
+    fn restore(&mut self, _node: &mut Node, snapshot: Value) -> Result<()> {
+        let state: State = serde_json::from_value(snapshot).context("restoring snapshot")?;
+        self.my = state.my;
+        self.theirs = state.theirs;
+        Ok(())
+    }
+
+    fn replay(&mut self, _node: &mut Node, record: Value) -> Result<()> {
+        match serde_json::from_value(record).context("replaying log")? {
+            Record::My(value) => {
+                self.my.insert(value);
+            }
+            Record::Theirs(values) => self.theirs.union_with(&values),
+        }
+        Ok(())
+    }
+
+    fn timer(&mut self, node: &mut Node, name: &str) -> Result<()> {
+        match name {
+            GOSSIP => self.gossip(node),
+            SYNC => self.sync(node),
+            FLUSH => self.flush(node),
+            _ => Ok(()),
+        }
+    }
+}
 
-    node.main()?;
+pub fn main() -> Result<()> {
+    let topology = Topology::from_env()?;
+
+    let make_handler = |node: &Node| {
+        let broadcaster = Broadcaster {
+            topology,
+            grid: Grid::new(),
+            neighbours: topology.neighbours(&node.id, &node.node_ids, &Grid::new()),
+            my: ValueSet::new(),
+            theirs: ValueSet::new(),
+            known: HashMap::new(),
+            gossip: Retrier::new(Duration::from_millis(500), Duration::from_secs(4)),
+            outbox: Outbox::new(BATCH_VALUES, BATCH_DELAY),
+            peers: node
+                .node_ids
+                .iter()
+                .filter(|id| **id != node.id)
+                .cloned()
+                .collect(),
+            // so that the nodes do not all start with the same peer
+            next_sync: node
+                .node_ids
+                .iter()
+                .position(|id| *id == node.id)
+                .unwrap_or(0),
+        };
 
-    timer.join().unwrap();
-    // TODO: fix anyhow
-    reader.join().unwrap();
+        Router::new(broadcaster)
+            .on("broadcast", Broadcaster::handle_broadcast)
+            .on("read", Broadcaster::handle_read)
+            .on("topology", Broadcaster::handle_topology)
+            .on("gossip", Broadcaster::handle_gossip)
+            // answers to earlier copies of a resent gossip, once the retrier
+            // has moved on to a new msg_id
+            .on("gossip_ok", |_, _: IgnoredAny, _| Ok(()))
+            .on("sync", Broadcaster::handle_sync)
+            .on("sync_ok", Broadcaster::handle_sync_ok)
+    };
 
-    Ok(())
+    match env::args().nth(1).as_deref() {
+        #[cfg(feature = "simulate")]
+        Some("simulate") => harness::cli::simulate(env::args().skip(2), make_handler),
+        #[cfg(not(feature = "simulate"))]
+        Some("simulate") => anyhow::bail!("built without the simulate feature"),
+        _ => Node::run(make_handler),
+    }
 }
@@ -1,61 +1,25 @@
-use anyhow::{bail, Context, Result};
-use flyio::{parse_message, send_message, take_init};
-use serde::{Deserialize, Serialize};
-use std::io::{self};
-
-#[derive(Deserialize, Serialize, Debug)]
-struct Echo<'a> {
-    msg_id: usize,
-    echo: &'a str,
-}
-
-#[derive(Deserialize, Serialize, Debug)]
-struct EchoOK<'a> {
-    msg_id: usize,
-    in_reply_to: usize,
-    echo: &'a str,
+use anyhow::Result;
+use flyio::{Body, Handler, Message, Node};
+
+#[derive(Body, Debug)]
+#[body(replies = BodyOut<'a>)]
+enum BodyIn {
+    #[reply(echo: &'a str)]
+    Echo { echo: String },
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-#[serde(tag = "type")]
-enum Body<'a> {
-    #[serde(borrow, rename = "echo")]
-    Echo(Echo<'a>),
-    #[serde(borrow, rename = "echo_ok")]
-    EchoOK(EchoOK<'a>),
-}
-
-pub fn main() -> Result<()> {
-    let mut lines = io::stdin().lines();
-    let mut stdout = io::stdout().lock();
+struct EchoNode;
 
-    let node = take_init(&mut lines, &mut stdout)?;
+impl Handler for EchoNode {
+    type Body = BodyIn;
 
-    let mut message_id: usize = 0;
-    let mut next_message_id = move || {
-        message_id += 1;
-        message_id
-    };
-
-    for line in lines {
-        let line = line.context("reading message")?;
-        let message = parse_message::<Body>(&line)?;
-
-        match message.body {
-            Body::Echo(body) => {
-                let outgoing = Body::EchoOK(EchoOK {
-                    msg_id: next_message_id(),
-                    in_reply_to: body.msg_id,
-                    echo: &body.echo,
-                });
-
-                send_message(&mut stdout, &node.id, message.src, outgoing)?;
-            }
-            Body::EchoOK(_) => {
-                bail!("unexpected echo_ok message")
-            }
+    fn handle(&mut self, node: &mut Node, message: Message<BodyIn>) -> Result<()> {
+        match &message.body {
+            BodyIn::Echo { echo } => node.reply(&message, BodyOut::echo_ok(echo)),
         }
     }
+}
 
-    Ok(())
+pub fn main() -> Result<()> {
+    Node::run(|_| EchoNode)
 }
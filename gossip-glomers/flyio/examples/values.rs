@@ -0,0 +1,151 @@
+use anyhow::{bail, Context, Result};
+use flyio::{Digest, ValueSet};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{self, BufRead};
+
+/// An operation to run, one JSON line of stdin. Sets are given in their
+/// compact form.
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Op {
+    /// Inserts the runs one after the other into an empty set.
+    InsertRuns {
+        runs: Vec<(i32, i32)>,
+    },
+    /// Reads a set from its compact form.
+    Parse {
+        set: Value,
+    },
+    Union {
+        a: ValueSet,
+        b: ValueSet,
+    },
+    Difference {
+        a: ValueSet,
+        b: ValueSet,
+    },
+    Intersection {
+        a: ValueSet,
+        b: ValueSet,
+    },
+    Within {
+        a: ValueSet,
+        first: i32,
+        last: i32,
+    },
+    /// The values of `a` in the digest buckets, which may be anything a
+    /// peer sent.
+    InBuckets {
+        a: ValueSet,
+        buckets: Vec<i64>,
+    },
+    /// Compares the digests of two sets.
+    Differing {
+        a: ValueSet,
+        b: ValueSet,
+    },
+    /// Heals replicas that diverged during a partition the way broadcast_d
+    /// does: `a` sends its digest, `b` answers with its values in the buckets
+    /// they disagree on and `a` gossips back what `b` lacks in those.
+    Sync {
+        a: ValueSet,
+        b: ValueSet,
+    },
+}
+
+/// Runs `ValueSet` and `Digest` operations read from stdin and prints each result as a
+/// JSON line, for `flyio/test.sh` to compare with what it should be. Stops
+/// on a result breaking the invariants of the set.
+fn main() -> Result<()> {
+    for line in io::stdin().lock().lines() {
+        let line = line.context("reading stdin")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let op: Op = serde_json::from_str(&line).with_context(|| format!("parsing {line}"))?;
+
+        let result = match op {
+            Op::InsertRuns { runs } => {
+                let mut set = ValueSet::new();
+                for (first, last) in runs {
+                    set.insert_run(first, last);
+                }
+                checked(set)
+            }
+            Op::Parse { set } => match serde_json::from_value(set) {
+                Ok(set) => checked(set),
+                Err(err) => Ok(json!({ "error": err.to_string() })),
+            },
+            Op::Union { a, b } => checked(a.union(&b)),
+            Op::Difference { a, b } => checked(a.difference(&b)),
+            Op::Intersection { a, b } => checked(a.intersection(&b)),
+            Op::Within { a, first, last } => checked(a.within(first, last)),
+            Op::InBuckets { a, buckets } => checked(a.in_buckets(&buckets)),
+            Op::Differing { a, b } => {
+                let (a, b) = (Digest::of(&a), Digest::of(&b));
+                Ok(json!({ "equal": a == b, "differing": a.differing(&b) }))
+            }
+            Op::Sync { mut a, mut b } => sync(&mut a, &mut b),
+        }
+        .with_context(|| format!("running {line}"))?;
+        println!("{result}");
+    }
+    Ok(())
+}
+
+/// The set and its length, once its runs are checked to be sorted, disjoint
+/// and not touching, to add up to its length and to survive a round trip
+/// through JSON.
+fn checked(set: ValueSet) -> Result<Value> {
+    let mut len = 0;
+    let mut previous: Option<i64> = None;
+    for run in set.runs() {
+        let (first, last) = (i64::from(*run.start()), i64::from(*run.end()));
+        if first > last {
+            bail!("empty run in {set:?}");
+        }
+        if previous.is_some_and(|previous| first <= previous + 1) {
+            bail!("runs of {set:?} overlap or touch");
+        }
+        previous = Some(last);
+        len += (last - first + 1) as u64;
+    }
+    if len != set.len() {
+        bail!("{set:?} has {len} values but a length of {}", set.len());
+    }
+
+    let json = serde_json::to_value(&set)?;
+    let back: ValueSet = serde_json::from_value(json.clone())?;
+    if back != set {
+        bail!("{set:?} reads back from {json} as {back:?}");
+    }
+
+    Ok(json!({ "set": json, "len": set.len() }))
+}
+
+/// The buckets exchanged and the values sent each way, once both replicas
+/// are checked to hold the same set afterwards.
+fn sync(a: &mut ValueSet, b: &mut ValueSet) -> Result<Value> {
+    let union = a.union(b);
+
+    let buckets = Digest::of(b).differing(&Digest::of(a));
+    let sent = b.in_buckets(&buckets);
+    let returned = a.in_buckets(&buckets).difference(&sent);
+    a.union_with(&sent);
+    b.union_with(&returned);
+
+    if a != b || *a != union {
+        bail!("replicas did not converge: {a:?} and {b:?}");
+    }
+    if !Digest::of(a).differing(&Digest::of(b)).is_empty() {
+        bail!("converged replicas still have differing digests");
+    }
+
+    Ok(json!({
+        "buckets": buckets,
+        "sent": sent.len(),
+        "returned": returned.len(),
+        "set": checked(a.clone())?,
+    }))
+}
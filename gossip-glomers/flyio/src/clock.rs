@@ -0,0 +1,53 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Where a node gets the time from: the system clock, or a virtual one that
+/// only moves when a [`crate::sim::Simulation`] advances it.
+///
+/// Code that should behave the same under simulation asks [`crate::Node::now`]
+/// instead of [`Instant::now`].
+#[derive(Clone, Debug, Default)]
+pub struct Clock(Option<Arc<VirtualTime>>);
+
+#[derive(Debug)]
+struct VirtualTime {
+    start: Instant,
+    elapsed_nanos: AtomicU64,
+}
+
+impl Clock {
+    pub fn system() -> Self {
+        Clock(None)
+    }
+
+    /// A clock frozen at `start` until advanced.
+    pub fn virtual_from(start: Instant) -> Self {
+        Clock(Some(Arc::new(VirtualTime {
+            start,
+            elapsed_nanos: AtomicU64::new(0),
+        })))
+    }
+
+    pub fn is_virtual(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub fn now(&self) -> Instant {
+        match &self.0 {
+            None => Instant::now(),
+            Some(time) => {
+                time.start + Duration::from_nanos(time.elapsed_nanos.load(Ordering::SeqCst))
+            }
+        }
+    }
+
+    /// Moves a virtual clock forward to `at`, it never goes back. The system
+    /// clock is left alone.
+    pub fn advance_to(&self, at: Instant) {
+        if let Some(time) = &self.0 {
+            let elapsed = at.saturating_duration_since(time.start).as_nanos() as u64;
+            time.elapsed_nanos.fetch_max(elapsed, Ordering::SeqCst);
+        }
+    }
+}
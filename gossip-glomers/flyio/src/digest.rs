@@ -0,0 +1,110 @@
+use crate::values::ValueSet;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+/// Consecutive values summarized by one bucket of a [`Digest`].
+const BUCKET_WIDTH: i64 = 1024;
+
+/// A summary of a [`ValueSet`]: how many values fall in each range of 1024
+/// consecutive values and a hash of them.
+///
+/// Two nodes compare digests to find the buckets they disagree on and then
+/// only exchange the values in those, see [`ValueSet::in_buckets`]. Dense sets
+/// of counter-issued values take one entry per thousand values.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "Vec<Bucket>", into = "Vec<Bucket>")]
+pub struct Digest {
+    /// Bucket to the count and hash of its values, empty buckets are left out.
+    buckets: BTreeMap<i64, (u64, u64)>,
+}
+
+impl Digest {
+    pub fn of(values: &ValueSet) -> Self {
+        let mut buckets = BTreeMap::new();
+        for run in values.runs() {
+            let (first, last) = (i64::from(*run.start()), i64::from(*run.end()));
+
+            // runs are canonical, so are the pieces they are cut into
+            let mut start = first;
+            while start <= last {
+                let bucket = start.div_euclid(BUCKET_WIDTH);
+                let end = last.min(bucket_last(bucket));
+                let (count, hash) = buckets.entry(bucket).or_insert((0u64, 0u64));
+                *count += (end - start + 1) as u64;
+                *hash = hash.wrapping_add(mix(start, end));
+                start = end + 1;
+            }
+        }
+
+        Digest { buckets }
+    }
+
+    /// Buckets whose values differ between the two sets.
+    pub fn differing(&self, other: &Digest) -> Vec<i64> {
+        let mut buckets: Vec<i64> = self
+            .buckets
+            .iter()
+            .filter(|(bucket, summary)| other.buckets.get(bucket) != Some(summary))
+            .map(|(&bucket, _)| bucket)
+            .collect();
+        buckets.extend(
+            other
+                .buckets
+                .keys()
+                .filter(|bucket| !self.buckets.contains_key(bucket)),
+        );
+        buckets.sort_unstable();
+        buckets
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+}
+
+/// A bucket on the wire: `[bucket, count, hash]`.
+type Bucket = (i64, u64, u64);
+
+impl From<Vec<Bucket>> for Digest {
+    fn from(buckets: Vec<Bucket>) -> Self {
+        let buckets = buckets
+            .into_iter()
+            .map(|(bucket, count, hash)| (bucket, (count, hash)))
+            .collect();
+        Digest { buckets }
+    }
+}
+
+impl From<Digest> for Vec<Bucket> {
+    fn from(digest: Digest) -> Self {
+        digest
+            .buckets
+            .into_iter()
+            .map(|(bucket, (count, hash))| (bucket, count, hash))
+            .collect()
+    }
+}
+
+fn bucket_last(bucket: i64) -> i64 {
+    bucket * BUCKET_WIDTH + BUCKET_WIDTH - 1
+}
+
+/// The values of `bucket`, none when it holds no `i32`: buckets off the wire
+/// can be anything.
+pub(crate) fn bucket_values(bucket: i64) -> Option<RangeInclusive<i32>> {
+    let buckets = i64::from(i32::MIN) / BUCKET_WIDTH..=i64::from(i32::MAX) / BUCKET_WIDTH;
+    if !buckets.contains(&bucket) {
+        return None;
+    }
+    // the range of i32 starts and ends on bucket boundaries
+    Some((bucket * BUCKET_WIDTH) as i32..=bucket_last(bucket) as i32)
+}
+
+/// Spreads the bits of a run, SplitMix64's finalizer.
+fn mix(first: i64, last: i64) -> u64 {
+    let mut z = ((first as u64) << 32) ^ (last as u64 & 0xffff_ffff);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,142 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Maelstrom's error codes, see
+/// <https://github.com/jepsen-io/maelstrom/blob/main/doc/protocol.md#errors>.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(from = "u32", into = "u32")]
+pub enum ErrorCode {
+    /// The request timed out, it may or may not have taken effect.
+    Timeout,
+    /// The destination node does not exist.
+    NodeNotFound,
+    /// The request type is not supported by the node.
+    NotSupported,
+    /// The operation definitely did not happen, retrying might help.
+    TemporarilyUnavailable,
+    /// The request could not be parsed.
+    MalformedRequest,
+    /// Something went wrong, the operation may or may not have happened.
+    Crash,
+    /// The operation definitely did not happen.
+    Abort,
+    KeyDoesNotExist,
+    KeyAlreadyExists,
+    PreconditionFailed,
+    TxnConflict,
+    /// Codes outside of Maelstrom's list, 1000 and up are for workloads.
+    Other(u32),
+}
+
+impl ErrorCode {
+    /// The code of an [`Error`] somewhere in `err`, if there is one.
+    pub fn of(err: &anyhow::Error) -> Option<ErrorCode> {
+        err.downcast_ref::<Error>().map(|error| error.code)
+    }
+
+    /// Whether the failed operation definitely did not take effect.
+    pub fn is_definite(self) -> bool {
+        !matches!(
+            self,
+            ErrorCode::Timeout | ErrorCode::Crash | ErrorCode::Other(_)
+        )
+    }
+}
+
+impl From<u32> for ErrorCode {
+    fn from(code: u32) -> Self {
+        match code {
+            0 => ErrorCode::Timeout,
+            1 => ErrorCode::NodeNotFound,
+            10 => ErrorCode::NotSupported,
+            11 => ErrorCode::TemporarilyUnavailable,
+            12 => ErrorCode::MalformedRequest,
+            13 => ErrorCode::Crash,
+            14 => ErrorCode::Abort,
+            20 => ErrorCode::KeyDoesNotExist,
+            21 => ErrorCode::KeyAlreadyExists,
+            22 => ErrorCode::PreconditionFailed,
+            30 => ErrorCode::TxnConflict,
+            code => ErrorCode::Other(code),
+        }
+    }
+}
+
+impl From<ErrorCode> for u32 {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::Timeout => 0,
+            ErrorCode::NodeNotFound => 1,
+            ErrorCode::NotSupported => 10,
+            ErrorCode::TemporarilyUnavailable => 11,
+            ErrorCode::MalformedRequest => 12,
+            ErrorCode::Crash => 13,
+            ErrorCode::Abort => 14,
+            ErrorCode::KeyDoesNotExist => 20,
+            ErrorCode::KeyAlreadyExists => 21,
+            ErrorCode::PreconditionFailed => 22,
+            ErrorCode::TxnConflict => 30,
+            ErrorCode::Other(code) => code,
+        }
+    }
+}
+
+/// An error to report to the sender of a request.
+///
+/// Returned from a [`crate::Handler`] (even wrapped in `anyhow` context) it
+/// makes the node reply with an `error` message instead of exiting. Error
+/// replies to [`crate::Node::rpc`] requests come back as this type too.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Error {
+    pub code: ErrorCode,
+    #[serde(default)]
+    pub text: String,
+}
+
+impl Error {
+    pub fn new(code: ErrorCode, text: impl Into<String>) -> Self {
+        Error {
+            code,
+            text: text.into(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error {} ({:?})", u32::from(self.code), self.code)?;
+        if !self.text.is_empty() {
+            write!(f, ": {}", self.text)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Keeps the code of the [`Error`] inside `err` and its context in the text,
+/// untyped errors are reported as a [`ErrorCode::Crash`]: the sender cannot
+/// know whether the operation took place.
+impl From<&anyhow::Error> for Error {
+    fn from(err: &anyhow::Error) -> Self {
+        let code = ErrorCode::of(err).unwrap_or(ErrorCode::Crash);
+        let text = err
+            .chain()
+            .map(|cause| match cause.downcast_ref::<Error>() {
+                Some(error) => error.text.clone(),
+                None => cause.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(": ");
+
+        Error::new(code, text)
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename = "error")]
+pub(crate) struct ErrorBody<'a> {
+    pub in_reply_to: usize,
+    pub code: ErrorCode,
+    pub text: &'a str,
+}
@@ -0,0 +1,670 @@
+//! Ways of spreading broadcast values between nodes, behind one
+//! [`GossipStrategy`] trait so they can be compared on the same workload.
+//!
+//! Every strategy talks with `gossip` messages told apart by their `kind`,
+//! see [`Gossip`], and only with the neighbours a [`Topology`](crate::Topology)
+//! gives them. Pushes, and plumtree's `ihave`s and grafts, go through a
+//! [`Retrier`] and must be acknowledged with `gossip_ok`: a lost one would
+//! leave a value missing for good. Everything else is sent once.
+
+use crate::digest::Digest;
+use crate::node::Node;
+use crate::retry::Retrier;
+use crate::rng::Rng;
+use crate::values::ValueSet;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long plumtree waits for a value it heard of to arrive before asking
+/// the node that has it.
+const GRAFT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Ticks random push keeps pushing a value it learnt.
+const PUSH_ROUNDS: usize = 3;
+
+/// What a `gossip` message carries.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Gossip {
+    /// Values for the receiver.
+    Push { messages: ValueSet },
+    /// Push-pull: the sender's digest, the receiver answers with a sync.
+    Pull { digest: Digest },
+    /// Push-pull: the sender's values in the buckets where the digests
+    /// differ, the receiver pushes back what the sender lacks of those.
+    Sync {
+        messages: ValueSet,
+        buckets: Vec<i64>,
+    },
+    /// Plumtree lazy push: the sender has these values.
+    IHave { messages: ValueSet },
+    /// Plumtree: the sender misses these values, and wants eager pushes from
+    /// now on.
+    Graft { messages: ValueSet },
+    /// Plumtree: the sender got a push it already had, no more eager pushes.
+    Prune,
+}
+
+impl Gossip {
+    fn values(&self) -> Option<&ValueSet> {
+        match self {
+            Gossip::Push { messages }
+            | Gossip::Sync { messages, .. }
+            | Gossip::IHave { messages }
+            | Gossip::Graft { messages } => Some(messages),
+            Gossip::Pull { .. } | Gossip::Prune => None,
+        }
+    }
+}
+
+/// An incoming `gossip` message.
+#[derive(Deserialize, Debug)]
+pub struct GossipIn {
+    /// Only set on pushes, which want a `gossip_ok`.
+    pub msg_id: Option<usize>,
+    #[serde(flatten)]
+    pub gossip: Gossip,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename = "gossip")]
+struct GossipOut {
+    #[serde(flatten)]
+    gossip: Gossip,
+}
+
+/// Counters shared by the strategies of every node in a process.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pushes: AtomicU64,
+    pulls: AtomicU64,
+    syncs: AtomicU64,
+    ihaves: AtomicU64,
+    grafts: AtomicU64,
+    prunes: AtomicU64,
+    /// Values carried by everything sent.
+    values: AtomicU64,
+    /// Values received that were already known.
+    duplicates: AtomicU64,
+}
+
+impl Metrics {
+    fn sent(&self, gossip: &Gossip) {
+        let counter = match gossip {
+            Gossip::Push { .. } => &self.pushes,
+            Gossip::Pull { .. } => &self.pulls,
+            Gossip::Sync { .. } => &self.syncs,
+            Gossip::IHave { .. } => &self.ihaves,
+            Gossip::Graft { .. } => &self.grafts,
+            Gossip::Prune => &self.prunes,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        let values = gossip.values().map_or(0, ValueSet::len);
+        self.values.fetch_add(values, Ordering::Relaxed);
+    }
+
+    fn received(&self, values: &ValueSet, seen: &ValueSet) {
+        let duplicates = values.len() - values.difference(seen).len();
+        self.duplicates.fetch_add(duplicates, Ordering::Relaxed);
+    }
+
+    /// Messages sent, not counting retries.
+    pub fn messages(&self) -> u64 {
+        [
+            &self.pushes,
+            &self.pulls,
+            &self.syncs,
+            &self.ihaves,
+            &self.grafts,
+            &self.prunes,
+        ]
+        .iter()
+        .map(|counter| counter.load(Ordering::Relaxed))
+        .sum()
+    }
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        write!(
+            f,
+            "{} messages ({} push, {} pull, {} sync, {} ihave, {} graft, {} prune), \
+             {} values sent, {} duplicates received",
+            self.messages(),
+            load(&self.pushes),
+            load(&self.pulls),
+            load(&self.syncs),
+            load(&self.ihaves),
+            load(&self.grafts),
+            load(&self.prunes),
+            load(&self.values),
+            load(&self.duplicates),
+        )
+    }
+}
+
+/// Spreads the values a node learns to every other node.
+pub trait GossipStrategy {
+    /// A client broadcast `value` to this node, it was new.
+    fn broadcast(&mut self, node: &mut Node, value: i32) -> Result<()>;
+
+    /// Handles `gossip` from `src`, `seen` is everything this node had before
+    /// it. Returns the values it carried.
+    fn receive(
+        &mut self,
+        node: &mut Node,
+        src: &str,
+        gossip: Gossip,
+        seen: &ValueSet,
+    ) -> Result<ValueSet>;
+
+    /// Runs on the gossip timer, `seen` is everything this node has.
+    fn tick(&mut self, node: &mut Node, seen: &ValueSet) -> Result<()>;
+
+    /// The topology changed, this node gossips with `neighbours` from now on.
+    fn neighbours(&mut self, neighbours: Vec<String>);
+}
+
+/// Which [`GossipStrategy`] to use.
+///
+/// Parsed from strings like `tree`, `push:3`, `push-pull` or `plumtree`, see
+/// [`Strategy::from_env`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// New values are pushed to every neighbour, and each node passes on
+    /// what was new to it to all its other neighbours. Over a tree topology
+    /// every value crosses every link once.
+    #[default]
+    Tree,
+    /// Epidemic: new values are pushed to `fanout` random neighbours for a
+    /// few ticks. Probabilistic, a value can miss a node.
+    RandomPush { fanout: usize },
+    /// Random push plus a digest exchange with a random neighbour every tick,
+    /// which repairs whatever the push missed.
+    PushPull { fanout: usize },
+    /// Eager pushes along a tree that prunes itself from duplicate
+    /// deliveries, lazy `ihave`s on the other links to repair it.
+    Plumtree,
+}
+
+impl Strategy {
+    /// Env var consulted by [`Strategy::from_env`].
+    pub const ENV: &'static str = "GOSSIP";
+
+    /// Reads the strategy from the `GOSSIP` env var, falling back to the
+    /// default when it is not set.
+    pub fn from_env() -> Result<Strategy> {
+        match std::env::var(Self::ENV) {
+            Ok(spec) => spec.parse(),
+            Err(std::env::VarError::NotPresent) => Ok(Strategy::default()),
+            Err(err) => Err(err).context(Self::ENV),
+        }
+    }
+
+    /// The strategy for `node` gossiping with `neighbours`, see
+    /// [`Topology::neighbours`](crate::Topology::neighbours), counting what it sends in `metrics`.
+    pub fn build(
+        self,
+        node: &Node,
+        neighbours: Vec<String>,
+        metrics: Arc<Metrics>,
+    ) -> Box<dyn GossipStrategy + Send> {
+        let wire = Wire {
+            retrier: Retrier::new(Duration::from_millis(500), Duration::from_secs(4)),
+            metrics,
+        };
+
+        match self {
+            Strategy::Tree => Box::new(Tree {
+                peers: neighbours,
+                fresh: ValueSet::new(),
+                wire,
+            }),
+            Strategy::RandomPush { fanout } => {
+                Box::new(RandomPush::new(node, neighbours, fanout, PUSH_ROUNDS, wire))
+            }
+            Strategy::PushPull { fanout } => Box::new(PushPull {
+                push: RandomPush::new(node, neighbours, fanout, 1, wire),
+            }),
+            Strategy::Plumtree => Box::new(Plumtree {
+                eager: neighbours.into_iter().collect(),
+                lazy: BTreeSet::new(),
+                fresh: ValueSet::new(),
+                unannounced: BTreeMap::new(),
+                announced: Vec::new(),
+                wire,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Strategy::Tree => write!(f, "tree"),
+            Strategy::RandomPush { fanout } => write!(f, "push:{fanout}"),
+            Strategy::PushPull { fanout } => write!(f, "push-pull:{fanout}"),
+            Strategy::Plumtree => write!(f, "plumtree"),
+        }
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let (name, param) = match spec.split_once(':') {
+            Some((name, param)) => {
+                let param = param
+                    .parse::<usize>()
+                    .with_context(|| format!("parsing gossip parameter in {spec:?}"))?;
+                (name, Some(param))
+            }
+            None => (spec, None),
+        };
+
+        Ok(match (name, param) {
+            ("tree", None) => Strategy::Tree,
+            ("push", fanout) => Strategy::RandomPush {
+                fanout: fanout.unwrap_or(3),
+            },
+            ("push-pull", fanout) => Strategy::PushPull {
+                fanout: fanout.unwrap_or(2),
+            },
+            ("plumtree", None) => Strategy::Plumtree,
+            _ => bail!("unknown gossip strategy {spec:?}, expected tree, push[:fanout], push-pull[:fanout] or plumtree"),
+        })
+    }
+}
+
+/// Sending gossip and counting it.
+struct Wire {
+    retrier: Retrier<GossipOut>,
+    metrics: Arc<Metrics>,
+}
+
+impl Wire {
+    /// Sends `gossip` until `dest` acknowledges it.
+    fn push(&mut self, node: &mut Node, dest: &str, gossip: Gossip) -> Result<()> {
+        self.metrics.sent(&gossip);
+        self.retrier.send(node, dest, GossipOut { gossip })
+    }
+
+    /// Sends `gossip` once.
+    fn send(&mut self, node: &mut Node, dest: &str, gossip: Gossip) -> Result<()> {
+        self.metrics.sent(&gossip);
+        node.send(dest, GossipOut { gossip })
+    }
+
+    fn poll(&mut self, node: &mut Node) -> Result<()> {
+        self.retrier.poll(node)?;
+        Ok(())
+    }
+}
+
+struct Tree {
+    peers: Vec<String>,
+    /// Broadcast values not yet handed to the gossip.
+    fresh: ValueSet,
+    wire: Wire,
+}
+
+impl Tree {
+    fn spread(&mut self, node: &mut Node, except: Option<&str>, values: &ValueSet) -> Result<()> {
+        for dest in &self.peers {
+            if Some(dest.as_str()) == except {
+                continue;
+            }
+            let push = Gossip::Push {
+                messages: values.clone(),
+            };
+            self.wire.push(node, dest, push)?;
+        }
+        Ok(())
+    }
+}
+
+impl GossipStrategy for Tree {
+    fn broadcast(&mut self, _node: &mut Node, value: i32) -> Result<()> {
+        self.fresh.insert(value);
+        Ok(())
+    }
+
+    fn receive(
+        &mut self,
+        node: &mut Node,
+        src: &str,
+        gossip: Gossip,
+        seen: &ValueSet,
+    ) -> Result<ValueSet> {
+        let Gossip::Push { messages } = gossip else {
+            return Ok(gossip.values().cloned().unwrap_or_default());
+        };
+        self.wire.metrics.received(&messages, seen);
+
+        // only what was new here, the rest went on when it first arrived
+        let new = messages.difference(seen);
+        if !new.is_empty() {
+            self.spread(node, Some(src), &new)?;
+        }
+        Ok(messages)
+    }
+
+    fn tick(&mut self, node: &mut Node, _seen: &ValueSet) -> Result<()> {
+        self.wire.poll(node)?;
+
+        // held back until there is someone to tell, e.g. before the
+        // `topology` message of a grid
+        if self.fresh.is_empty() || self.peers.is_empty() {
+            return Ok(());
+        }
+        let fresh = std::mem::take(&mut self.fresh);
+        self.spread(node, None, &fresh)
+    }
+
+    fn neighbours(&mut self, neighbours: Vec<String>) {
+        self.peers = neighbours;
+    }
+}
+
+struct RandomPush {
+    peers: Vec<String>,
+    fanout: usize,
+    rounds: usize,
+    /// Values learnt since the last tick.
+    fresh: ValueSet,
+    /// Values still being pushed, with the ticks they have left.
+    hot: Vec<(ValueSet, usize)>,
+    rng: Rng,
+    wire: Wire,
+}
+
+impl RandomPush {
+    fn new(node: &Node, peers: Vec<String>, fanout: usize, rounds: usize, wire: Wire) -> Self {
+        RandomPush {
+            peers,
+            fanout,
+            rounds,
+            fresh: ValueSet::new(),
+            hot: Vec::new(),
+            // per node, yet the same in every run so that simulations replay
+            rng: Rng::new(seed(&node.id)),
+            wire,
+        }
+    }
+
+    fn random_peers(&mut self, count: usize) -> Vec<String> {
+        let mut peers = self.peers.clone();
+        self.rng.shuffle(&mut peers);
+        peers.truncate(count);
+        peers
+    }
+
+    fn learn(&mut self, messages: &ValueSet, seen: &ValueSet) {
+        self.wire.metrics.received(messages, seen);
+        self.fresh.union_with(&messages.difference(seen));
+    }
+}
+
+impl GossipStrategy for RandomPush {
+    fn broadcast(&mut self, _node: &mut Node, value: i32) -> Result<()> {
+        self.fresh.insert(value);
+        Ok(())
+    }
+
+    fn receive(
+        &mut self,
+        _node: &mut Node,
+        _src: &str,
+        gossip: Gossip,
+        seen: &ValueSet,
+    ) -> Result<ValueSet> {
+        let messages = gossip.values().cloned().unwrap_or_default();
+        if let Gossip::Push { .. } = gossip {
+            self.learn(&messages, seen);
+        }
+        Ok(messages)
+    }
+
+    fn tick(&mut self, node: &mut Node, _seen: &ValueSet) -> Result<()> {
+        self.wire.poll(node)?;
+
+        if !self.fresh.is_empty() {
+            self.hot
+                .push((std::mem::take(&mut self.fresh), self.rounds));
+        }
+        if self.hot.is_empty() {
+            return Ok(());
+        }
+
+        let mut messages = ValueSet::new();
+        for (values, rounds) in &mut self.hot {
+            messages.union_with(values);
+            *rounds -= 1;
+        }
+        self.hot.retain(|(_, rounds)| *rounds > 0);
+
+        for dest in self.random_peers(self.fanout) {
+            let push = Gossip::Push {
+                messages: messages.clone(),
+            };
+            self.wire.push(node, &dest, push)?;
+        }
+        Ok(())
+    }
+
+    fn neighbours(&mut self, neighbours: Vec<String>) {
+        self.peers = neighbours;
+    }
+}
+
+struct PushPull {
+    push: RandomPush,
+}
+
+impl GossipStrategy for PushPull {
+    fn broadcast(&mut self, node: &mut Node, value: i32) -> Result<()> {
+        self.push.broadcast(node, value)
+    }
+
+    fn receive(
+        &mut self,
+        node: &mut Node,
+        src: &str,
+        gossip: Gossip,
+        seen: &ValueSet,
+    ) -> Result<ValueSet> {
+        match gossip {
+            Gossip::Pull { digest } => {
+                let buckets = Digest::of(seen).differing(&digest);
+                if !buckets.is_empty() {
+                    let sync = Gossip::Sync {
+                        messages: seen.in_buckets(&buckets),
+                        buckets,
+                    };
+                    self.push.wire.send(node, src, sync)?;
+                }
+                Ok(ValueSet::new())
+            }
+            Gossip::Sync { messages, buckets } => {
+                let missing = seen.in_buckets(&buckets).difference(&messages);
+                if !missing.is_empty() {
+                    let push = Gossip::Push { messages: missing };
+                    self.push.wire.push(node, src, push)?;
+                }
+                self.push.learn(&messages, seen);
+                Ok(messages)
+            }
+            gossip => self.push.receive(node, src, gossip, seen),
+        }
+    }
+
+    fn tick(&mut self, node: &mut Node, seen: &ValueSet) -> Result<()> {
+        self.push.tick(node, seen)?;
+
+        if let Some(dest) = self.push.random_peers(1).pop() {
+            let pull = Gossip::Pull {
+                digest: Digest::of(seen),
+            };
+            self.push.wire.send(node, &dest, pull)?;
+        }
+        Ok(())
+    }
+
+    fn neighbours(&mut self, neighbours: Vec<String>) {
+        self.push.neighbours(neighbours);
+    }
+}
+
+struct Plumtree {
+    /// Peers new values are pushed to right away, all the neighbours to
+    /// begin with.
+    eager: BTreeSet<String>,
+    /// Peers only told about new values.
+    lazy: BTreeSet<String>,
+    fresh: ValueSet,
+    /// New values each lazy peer hears of on the next tick, by the peers that
+    /// were lazy when the values came: one grafted in between still has to
+    /// hear of them.
+    unannounced: BTreeMap<String, ValueSet>,
+    /// Values lazy peers have and this node does not, by when they are
+    /// grafted if they still did not arrive.
+    announced: Vec<(Instant, String, ValueSet)>,
+    wire: Wire,
+}
+
+impl Plumtree {
+    fn spread(&mut self, node: &mut Node, except: Option<&str>, values: &ValueSet) -> Result<()> {
+        let eager: Vec<String> = self
+            .eager
+            .iter()
+            .filter(|peer| Some(peer.as_str()) != except)
+            .cloned()
+            .collect();
+        for dest in eager {
+            let push = Gossip::Push {
+                messages: values.clone(),
+            };
+            self.wire.push(node, &dest, push)?;
+        }
+
+        for peer in &self.lazy {
+            if Some(peer.as_str()) != except {
+                let unannounced = self.unannounced.entry(peer.clone()).or_default();
+                unannounced.union_with(values);
+            }
+        }
+        Ok(())
+    }
+
+    fn make_eager(&mut self, peer: &str) {
+        self.lazy.remove(peer);
+        self.eager.insert(peer.into());
+    }
+}
+
+impl GossipStrategy for Plumtree {
+    fn broadcast(&mut self, _node: &mut Node, value: i32) -> Result<()> {
+        self.fresh.insert(value);
+        Ok(())
+    }
+
+    fn receive(
+        &mut self,
+        node: &mut Node,
+        src: &str,
+        gossip: Gossip,
+        seen: &ValueSet,
+    ) -> Result<ValueSet> {
+        match gossip {
+            Gossip::Push { messages } => {
+                self.wire.metrics.received(&messages, seen);
+                let new = messages.difference(seen);
+                if new.is_empty() {
+                    if self.eager.remove(src) {
+                        self.lazy.insert(src.into());
+                        self.wire.send(node, src, Gossip::Prune)?;
+                    }
+                } else {
+                    self.make_eager(src);
+                    self.spread(node, Some(src), &new)?;
+                }
+                Ok(messages)
+            }
+            Gossip::IHave { messages } => {
+                let missing = messages.difference(seen);
+                if !missing.is_empty() {
+                    let at = node.now() + GRAFT_TIMEOUT;
+                    self.announced.push((at, src.into(), missing));
+                }
+                Ok(ValueSet::new())
+            }
+            Gossip::Graft { messages } => {
+                self.make_eager(src);
+                let push = Gossip::Push {
+                    messages: messages.intersection(seen),
+                };
+                self.wire.push(node, src, push)?;
+                Ok(ValueSet::new())
+            }
+            Gossip::Prune => {
+                if self.eager.remove(src) {
+                    self.lazy.insert(src.into());
+                }
+                Ok(ValueSet::new())
+            }
+            gossip => Ok(gossip.values().cloned().unwrap_or_default()),
+        }
+    }
+
+    fn tick(&mut self, node: &mut Node, seen: &ValueSet) -> Result<()> {
+        self.wire.poll(node)?;
+
+        let alone = self.eager.is_empty() && self.lazy.is_empty();
+        if !self.fresh.is_empty() && !alone {
+            let fresh = std::mem::take(&mut self.fresh);
+            self.spread(node, None, &fresh)?;
+        }
+
+        for (dest, messages) in std::mem::take(&mut self.unannounced) {
+            self.wire.push(node, &dest, Gossip::IHave { messages })?;
+        }
+
+        let now = node.now();
+        let (due, waiting) = std::mem::take(&mut self.announced)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(at, ..)| *at <= now);
+        self.announced = waiting;
+        for (_, src, values) in due {
+            let missing = values.difference(seen);
+            if !missing.is_empty() {
+                self.make_eager(&src);
+                self.wire
+                    .push(node, &src, Gossip::Graft { messages: missing })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Pruned links stay lazy as long as they remain in the topology.
+    fn neighbours(&mut self, neighbours: Vec<String>) {
+        let neighbours: BTreeSet<String> = neighbours.into_iter().collect();
+        self.lazy.retain(|peer| neighbours.contains(peer));
+        self.eager = neighbours.difference(&self.lazy).cloned().collect();
+    }
+}
+
+/// FNV-1a of a node id.
+fn seed(id: &str) -> u64 {
+    id.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
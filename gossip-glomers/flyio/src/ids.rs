@@ -0,0 +1,275 @@
+//! Unique ids in a few formats, unique across the nodes of a cluster and
+//! across restarts of each of them.
+//!
+//! Every node gets its own space through its index in `node_ids`. Within a
+//! node, [`IdGenerator`] logs a [`Reservation`] with [`Node::log`] every so
+//! often, and resumes past it after a restart.
+
+use crate::node::Node;
+use crate::rng::Rng;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Snowflake timestamps count milliseconds from 2020-01-01T00:00:00Z, which
+/// lasts their 41 bits until 2089.
+const SNOWFLAKE_EPOCH_MS: u64 = 1_577_836_800_000;
+const SNOWFLAKE_NODE_BITS: u32 = 10;
+const SNOWFLAKE_SEQUENCE_BITS: u32 = 12;
+
+const ULID_NODE_BITS: u32 = 16;
+
+/// How far ids go before the next [`Reservation`] is logged: milliseconds
+/// for the timestamped formats, ids for the counter.
+const RESERVE_MS: u64 = 10_000;
+const RESERVE_IDS: u64 = 1000;
+
+/// Crockford's base32 alphabet, no I, L, O or U.
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// An id as it goes in `generate_ok`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum Id {
+    Number(u64),
+    Text(String),
+}
+
+impl fmt::Display for Id {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Id::Number(id) => write!(f, "{id}"),
+            Id::Text(id) => f.write_str(id),
+        }
+    }
+}
+
+/// What ids an [`IdGenerator`] hands out.
+///
+/// Parsed from `snowflake`, `ulid` or `counter`, see [`IdFormat::from_env`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IdFormat {
+    /// 64-bit numbers: 41 bits of milliseconds since 2020, 10 bits of node
+    /// index and 12 bits of sequence within the millisecond. Up to 1024
+    /// nodes.
+    Snowflake,
+    /// 26 characters of Crockford base32 that sort by time: 48 bits of
+    /// milliseconds since the Unix epoch, 16 bits of node index and 64 bits
+    /// of sequence, which starts at random every millisecond.
+    Ulid,
+    /// `<node>-<n>` counting from 1 on every node.
+    #[default]
+    Counter,
+}
+
+impl IdFormat {
+    /// Env var consulted by [`IdFormat::from_env`].
+    pub const ENV: &'static str = "ID_FORMAT";
+
+    /// Reads the format from the `ID_FORMAT` env var, falling back to the
+    /// default when it is not set.
+    pub fn from_env() -> Result<IdFormat> {
+        match std::env::var(Self::ENV) {
+            Ok(spec) => spec.parse(),
+            Err(std::env::VarError::NotPresent) => Ok(IdFormat::default()),
+            Err(err) => Err(err).context(Self::ENV),
+        }
+    }
+}
+
+impl FromStr for IdFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        Ok(match spec {
+            "snowflake" => IdFormat::Snowflake,
+            "ulid" => IdFormat::Ulid,
+            "counter" => IdFormat::Counter,
+            _ => bail!("unknown id format {spec:?}, expected snowflake, ulid or counter"),
+        })
+    }
+}
+
+/// What an [`IdGenerator`] logs, to resume from after a restart.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reservation {
+    /// The timestamp of the last id, or the last count, when it was logged.
+    pub last: u64,
+    /// No id goes this far before the next reservation is logged.
+    pub until: u64,
+}
+
+/// Hands out ids of one [`IdFormat`] for a node.
+///
+/// The timestamped formats never go back in time: when the clock does not
+/// move or moves back, ids keep the last timestamp and count up the
+/// sequence, moving on to the next millisecond when the sequence runs out.
+/// That timestamp is logged whenever it gets ahead of the clock, and a
+/// restart resumes from the later of the clock and the last one logged.
+/// Anything handed out after that carried the time it was handed out at,
+/// which is behind the clock of the restarted node unless that clock went
+/// back.
+///
+/// The counter has no clock to go by and resumes at the end of the last
+/// reservation.
+///
+/// Hand the [`Reservation`]s [`IdGenerator::next`] logs with [`Node::log`]
+/// back to [`IdGenerator::recover`] from
+/// [`Handler::replay`](crate::Handler::replay), and the one from
+/// [`IdGenerator::reservation`] in snapshots.
+pub struct IdGenerator {
+    format: IdFormat,
+    node_id: String,
+    node_index: u64,
+    /// The timestamp of the last id, or the last count.
+    last: u64,
+    sequence: u64,
+    /// What was logged last, nothing yet since a restart.
+    logged: Option<Reservation>,
+    rng: Rng,
+}
+
+impl IdGenerator {
+    /// Fails when `node` is not one of its own `node_ids`, it would have no
+    /// id space of its own, or when the format has no room for its index.
+    pub fn new(format: IdFormat, node: &Node) -> Result<Self> {
+        let Some(node_index) = node.node_ids.iter().position(|id| *id == node.id) else {
+            bail!(
+                "{} is not one of the cluster's node ids {:?}",
+                node.id,
+                node.node_ids
+            );
+        };
+        let node_index = node_index as u64;
+
+        match format {
+            IdFormat::Snowflake if node_index >> SNOWFLAKE_NODE_BITS != 0 => {
+                bail!(
+                    "snowflake ids have room for 1024 nodes, {} is too many",
+                    node.id
+                );
+            }
+            IdFormat::Ulid if node_index >> ULID_NODE_BITS != 0 => {
+                bail!(
+                    "ulid ids have room for 65536 nodes, {} is too many",
+                    node.id
+                );
+            }
+            _ => {}
+        }
+
+        Ok(IdGenerator {
+            format,
+            node_id: node.id.clone(),
+            node_index,
+            last: 0,
+            sequence: 0,
+            logged: None,
+            rng: Rng::new(Rng::seed_from_time()),
+        })
+    }
+
+    pub fn next(&mut self, node: &mut Node) -> Result<Id> {
+        match self.format {
+            IdFormat::Snowflake => {
+                let now = now_ms().saturating_sub(SNOWFLAKE_EPOCH_MS);
+                self.advance(now, (1 << SNOWFLAKE_SEQUENCE_BITS) - 1, 0);
+                self.reserve(node, Some(now), RESERVE_MS)?;
+
+                let id = self.last << (SNOWFLAKE_NODE_BITS + SNOWFLAKE_SEQUENCE_BITS)
+                    | self.node_index << SNOWFLAKE_SEQUENCE_BITS
+                    | self.sequence;
+                Ok(Id::Number(id))
+            }
+            IdFormat::Ulid => {
+                // leaves the sequence half its range to count up in
+                let start = self.rng.next_u64() >> 1;
+                let now = now_ms();
+                self.advance(now, u64::MAX, start);
+                self.reserve(node, Some(now), RESERVE_MS)?;
+
+                let id = u128::from(self.last) << 80
+                    | u128::from(self.node_index) << 64
+                    | u128::from(self.sequence);
+                Ok(Id::Text(crockford(id)))
+            }
+            IdFormat::Counter => {
+                self.last += 1;
+                self.reserve(node, None, RESERVE_IDS)?;
+                Ok(Id::Text(format!("{}-{}", self.node_id, self.last)))
+            }
+        }
+    }
+
+    /// Takes back a reservation [`IdGenerator::next`] logged. The next id
+    /// logs a new one.
+    pub fn recover(&mut self, reservation: Reservation) {
+        let resume = match self.format {
+            IdFormat::Snowflake | IdFormat::Ulid => reservation.last,
+            IdFormat::Counter => reservation.until,
+        };
+        self.last = self.last.max(resume);
+    }
+
+    /// Where ids stand, for a snapshot.
+    pub fn reservation(&self) -> Reservation {
+        Reservation {
+            last: self.last,
+            until: self.logged.map_or(self.last, |logged| logged.until),
+        }
+    }
+
+    /// Moves on to `now` with the sequence at `start`, or to the next
+    /// sequence number when the clock did not move forward.
+    fn advance(&mut self, now: u64, max_sequence: u64, start: u64) {
+        if now > self.last {
+            self.last = now;
+            self.sequence = start;
+        } else if self.sequence < max_sequence {
+            self.sequence += 1;
+        } else {
+            self.last += 1;
+            self.sequence = start;
+        }
+    }
+
+    /// Logs a new reservation `ahead` of the last id once that reaches the
+    /// logged one, or gets ahead of the clock `now` past the logged one.
+    fn reserve(&mut self, node: &mut Node, now: Option<u64>, ahead: u64) -> Result<()> {
+        let due = match self.logged {
+            None => true,
+            Some(logged) => {
+                self.last >= logged.until
+                    || now.is_some_and(|now| self.last > now && self.last > logged.last)
+            }
+        };
+        if due {
+            let reservation = Reservation {
+                last: self.last,
+                until: self.last + ahead,
+            };
+            node.log(&reservation)?;
+            self.logged = Some(reservation);
+        }
+        Ok(())
+    }
+}
+
+/// Milliseconds since the Unix epoch, a clock set before it counts as a
+/// regression.
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// The 128 bits of `id` as 26 base32 characters, most significant first.
+fn crockford(id: u128) -> String {
+    (0..26)
+        .rev()
+        .map(|digit| char::from(CROCKFORD[(id >> (digit * 5)) as usize & 31]))
+        .collect()
+}
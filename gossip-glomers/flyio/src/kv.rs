@@ -0,0 +1,130 @@
+use crate::error::ErrorCode;
+use crate::node::Node;
+use anyhow::{bail, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+enum Request<'a, T> {
+    #[serde(rename = "read")]
+    Read { key: &'a str },
+    #[serde(rename = "write")]
+    Write { key: &'a str, value: T },
+    #[serde(rename = "cas")]
+    Cas {
+        key: &'a str,
+        from: T,
+        to: T,
+        create_if_not_exists: bool,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type")]
+enum Reply {
+    #[serde(rename = "read_ok")]
+    ReadOK { value: Value },
+    #[serde(rename = "write_ok")]
+    WriteOK,
+    #[serde(rename = "cas_ok")]
+    CasOK,
+}
+
+/// Client of one of Maelstrom's key-value services.
+///
+/// Every call blocks until the service replies or the node's RPC timeout
+/// expires. Failures the service reports come back as [`crate::Error`].
+#[derive(Clone, Copy, Debug)]
+pub struct Kv {
+    service: &'static str,
+}
+
+impl Kv {
+    /// Sequentially consistent store.
+    pub fn seq() -> Kv {
+        Kv { service: "seq-kv" }
+    }
+
+    /// Linearizable store.
+    pub fn lin() -> Kv {
+        Kv { service: "lin-kv" }
+    }
+
+    /// Last-write-wins store.
+    pub fn lww() -> Kv {
+        Kv { service: "lww-kv" }
+    }
+
+    fn call<T>(&self, node: &mut Node, request: Request<T>) -> Result<Reply>
+    where
+        T: Serialize,
+    {
+        let reply = node
+            .rpc(self.service, request)?
+            .wait::<Reply>()
+            .with_context(|| format!("calling {}", self.service))?;
+
+        Ok(reply.body)
+    }
+
+    /// The value under `key`, `None` if there is no such key.
+    pub fn read<T>(&self, node: &mut Node, key: &str) -> Result<Option<T>>
+    where
+        T: DeserializeOwned,
+    {
+        match self.call::<()>(node, Request::Read { key }) {
+            Ok(Reply::ReadOK { value }) => Ok(Some(
+                serde_json::from_value(value).with_context(|| format!("parsing value of {key}"))?,
+            )),
+            Ok(reply) => unexpected(self.service, reply),
+            Err(err) if ErrorCode::of(&err) == Some(ErrorCode::KeyDoesNotExist) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn write<T>(&self, node: &mut Node, key: &str, value: T) -> Result<()>
+    where
+        T: Serialize,
+    {
+        match self.call(node, Request::Write { key, value })? {
+            Reply::WriteOK => Ok(()),
+            reply => unexpected(self.service, reply),
+        }
+    }
+
+    /// Sets `key` to `to` if it currently holds `from`, returns `false` when
+    /// it does not (or does not exist and `create_if_not_exists` is off).
+    pub fn cas<T>(
+        &self,
+        node: &mut Node,
+        key: &str,
+        from: T,
+        to: T,
+        create_if_not_exists: bool,
+    ) -> Result<bool>
+    where
+        T: Serialize,
+    {
+        let request = Request::Cas {
+            key,
+            from,
+            to,
+            create_if_not_exists,
+        };
+
+        match self.call(node, request) {
+            Ok(Reply::CasOK) => Ok(true),
+            Ok(reply) => unexpected(self.service, reply),
+            Err(err) => match ErrorCode::of(&err) {
+                Some(ErrorCode::PreconditionFailed | ErrorCode::KeyDoesNotExist) => Ok(false),
+                _ => Err(err),
+            },
+        }
+    }
+}
+
+fn unexpected<T>(service: &str, reply: Reply) -> Result<T> {
+    bail!("unexpected reply from {service}: {reply:?}")
+}
@@ -0,0 +1,36 @@
+mod clock;
+mod digest;
+mod error;
+pub mod gossip;
+mod ids;
+mod kv;
+mod message;
+mod node;
+mod outbox;
+mod retry;
+mod rng;
+mod router;
+mod rpc;
+pub mod sim;
+mod store;
+mod timer;
+pub mod topology;
+mod values;
+
+pub use clock::Clock;
+pub use digest::Digest;
+pub use error::{Error, ErrorCode};
+pub use flyio_derive::Body;
+pub use ids::{Id, IdFormat, IdGenerator, Reservation};
+pub use kv::Kv;
+pub use message::{parse_message, send_message, Body, Envelope, Message, ReplyBody};
+pub use node::{Event, Handler, Node, DEFAULT_RPC_TIMEOUT};
+pub use outbox::Outbox;
+pub use retry::Retrier;
+pub use rng::Rng;
+pub use router::{Reply, Router, Unrouted};
+pub use rpc::Rpc;
+pub use store::STATE_DIR;
+pub use timer::Timer;
+pub use topology::Topology;
+pub use values::{FlatValues, ValueSet};
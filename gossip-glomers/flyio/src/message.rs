@@ -0,0 +1,185 @@
+use crate::error::{Error, ErrorCode};
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::borrow::Cow;
+use std::io::Write;
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Message<T> {
+    pub src: String,
+    pub dest: String,
+    /// The `msg_id` of the body, for bodies that leave it out of their type.
+    #[serde(skip)]
+    pub msg_id: Option<usize>,
+    /// The `in_reply_to` of the body, likewise.
+    #[serde(skip)]
+    pub in_reply_to: Option<usize>,
+    pub body: T,
+}
+
+impl<T> Message<T> {
+    /// The `msg_id` a reply to this message goes `in_reply_to`.
+    pub fn request_id(&self) -> Result<usize> {
+        request_id(&self.src, self.msg_id)
+    }
+}
+
+/// `msg_id` of a request from `src`, which has to have one to be replied to.
+pub(crate) fn request_id(src: &str, msg_id: Option<usize>) -> Result<usize> {
+    msg_id.ok_or_else(|| {
+        let text = format!("{src} sent a request without a msg_id");
+        Error::new(ErrorCode::MalformedRequest, text).into()
+    })
+}
+
+/// The body of the messages a [`Handler`](crate::Handler) receives, usually
+/// an enum with a variant per message type.
+///
+/// `#[derive(Body)]` implements it, a hand-written `Deserialize` lists the
+/// types it takes next to it.
+pub trait Body: DeserializeOwned {
+    /// The `type` of every message that decodes into this body. A message of
+    /// any other type is answered with `not-supported`, one of these that does
+    /// not decode with `malformed-request`.
+    const KINDS: &'static [&'static str];
+}
+
+/// A reply that carries its own `msg_id` and `in_reply_to`, like the replies
+/// `#[derive(Body)]` generates, for [`Node::reply`](crate::Node::reply) to
+/// fill in.
+pub trait ReplyBody: Serialize {
+    fn set_ids(&mut self, msg_id: usize, in_reply_to: usize);
+}
+
+#[derive(Serialize)]
+struct MessageRef<'a, T> {
+    src: &'a str,
+    dest: &'a str,
+    body: T,
+}
+
+/// A message parsed only as far as routing it takes, borrowing from its line.
+///
+/// `src`, `dest` and the `type`, `msg_id` and `in_reply_to` of the body are
+/// read without allocating, the body is kept as raw JSON until whoever the
+/// message is for decodes it with [`Envelope::decode`].
+#[derive(Debug)]
+pub struct Envelope<'a> {
+    pub src: Cow<'a, str>,
+    pub dest: Cow<'a, str>,
+    pub kind: Option<Cow<'a, str>>,
+    pub msg_id: Option<usize>,
+    pub in_reply_to: Option<usize>,
+    pub body: &'a RawValue,
+}
+
+#[derive(Deserialize)]
+struct RawMessage<'a> {
+    #[serde(borrow)]
+    src: Cow<'a, str>,
+    #[serde(borrow)]
+    dest: Cow<'a, str>,
+    #[serde(borrow)]
+    body: &'a RawValue,
+}
+
+#[derive(Deserialize)]
+struct Header<'a> {
+    #[serde(rename = "type", borrow)]
+    kind: Option<Cow<'a, str>>,
+    msg_id: Option<usize>,
+    in_reply_to: Option<usize>,
+}
+
+impl<'a> Envelope<'a> {
+    pub fn parse(line: &'a str) -> Result<Envelope<'a>> {
+        let message: RawMessage = serde_json::from_str(line).context("parsing message JSON")?;
+        let header: Header =
+            serde_json::from_str(message.body.get()).context("parsing message body JSON")?;
+
+        Ok(Envelope {
+            src: message.src,
+            dest: message.dest,
+            kind: header.kind,
+            msg_id: header.msg_id,
+            in_reply_to: header.in_reply_to,
+            body: message.body,
+        })
+    }
+
+    /// Deserializes the body as `T`, the only step that copies out of the
+    /// line.
+    pub fn decode<T>(&self) -> serde_json::Result<Message<T>>
+    where
+        T: Deserialize<'a>,
+    {
+        Ok(Message {
+            src: self.src.to_string(),
+            dest: self.dest.to_string(),
+            msg_id: self.msg_id,
+            in_reply_to: self.in_reply_to,
+            body: serde_json::from_str(self.body.get())?,
+        })
+    }
+}
+
+/// A line together with its [`Envelope`], parsed once and then handed
+/// around as one, e.g. from the stdin reader to the thread handling it.
+///
+/// The envelope borrows from the line this owns, nothing is copied out of it
+/// until a handler decodes the body.
+#[derive(Debug)]
+pub(crate) struct Incoming {
+    // dropped before the line it borrows from
+    envelope: Envelope<'static>,
+    /// Only kept alive for `envelope`, never touched again.
+    _line: String,
+}
+
+impl Incoming {
+    pub(crate) fn parse(line: String) -> Result<Incoming> {
+        // SAFETY: the text lives in the heap buffer of `line`, which stays
+        // where it is when the `String` moves and is neither changed nor
+        // freed before `envelope` is dropped. The envelope is only lent out
+        // for as long as `self` is borrowed.
+        let text: &'static str = unsafe { &*(line.as_str() as *const str) };
+        let envelope = Envelope::parse(text)?;
+        Ok(Incoming {
+            envelope,
+            _line: line,
+        })
+    }
+
+    pub(crate) fn envelope(&self) -> &Envelope<'_> {
+        &self.envelope
+    }
+}
+
+pub fn parse_message<'a, T>(line: &'a str) -> Result<Message<T>>
+where
+    T: Deserialize<'a>,
+{
+    Envelope::parse(line)?
+        .decode()
+        .context("parsing message body JSON")
+}
+
+/// Writes one message as a single JSON line.
+///
+/// The line is serialized up front and written in one go, so concurrent
+/// writers sharing the same stdout never interleave halves of a message.
+pub fn send_message<W, T>(out: &mut W, src: &str, dest: &str, body: T) -> Result<()>
+where
+    W: Write + ?Sized,
+    T: Serialize,
+{
+    let message = MessageRef { src, dest, body };
+
+    let mut line = serde_json::to_vec(&message).context("serializing message JSON")?;
+    line.push(b'\n');
+
+    out.write_all(&line)?;
+    Ok(out.flush()?)
+}
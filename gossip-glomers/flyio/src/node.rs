@@ -0,0 +1,817 @@
+use crate::clock::Clock;
+use crate::error::{Error, ErrorBody, ErrorCode};
+use crate::message::{request_id, send_message, Body, Envelope, Incoming, Message, ReplyBody};
+use crate::rng::Rng;
+use crate::rpc::{Pending, Rpc};
+use crate::store::{Store, STATE_DIR};
+use crate::timer::{Due, Timer, Timers};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::env;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long [`Node::rpc`] waits for a reply unless told otherwise.
+pub const DEFAULT_RPC_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Records logged with [`Node::log`] after which the runtime asks the handler
+/// for a [`Handler::snapshot`].
+const SNAPSHOT_EVERY: usize = 1000;
+
+#[derive(Deserialize, Debug)]
+struct Init {
+    msg_id: usize,
+    node_id: String,
+    node_ids: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct InitOK {
+    in_reply_to: usize,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type")]
+enum InitIn {
+    #[serde(rename = "init")]
+    Init(Init),
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+enum InitOut {
+    #[serde(rename = "init_ok")]
+    InitOK(InitOK),
+}
+
+/// The `init` a node was built from, acknowledged by [`Node::ready`].
+pub(crate) struct InitRequest {
+    src: String,
+    msg_id: usize,
+}
+
+/// A reply body of a [`Router`](crate::Router) handler, with the fields its
+/// type leaves out.
+#[derive(Serialize)]
+struct RoutedReply<'a, T> {
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    kind: Option<&'a str>,
+    msg_id: usize,
+    in_reply_to: usize,
+    #[serde(flatten)]
+    body: T,
+}
+
+/// What the runtime feeds a [`Handler`].
+#[derive(Debug)]
+pub enum Event<B> {
+    Message(Message<B>),
+    /// A timer set with [`Node::set_timer`] fired.
+    Timer(String),
+    /// An [`Rpc`] still waiting for its reply reached its timeout.
+    RpcTimeout {
+        msg_id: usize,
+        dest: String,
+    },
+    /// Input is over, the node is about to stop.
+    Shutdown,
+}
+
+/// Workload logic plugged into the [`Node`] runtime.
+///
+/// `Body` is the tagged enum of every message type the workload expects to
+/// receive after `init`, others are answered with a `not-supported` error.
+/// Wrapped in a [`Router`](crate::Router), the handler takes messages by
+/// type in functions of their own instead.
+///
+/// Every [`Event`] goes through [`Handler::event`], which hands it to the
+/// method for its kind unless overridden.
+///
+/// Returning an [`Error`] from [`Handler::handle`] replies to the request with
+/// it, any other error stops the node.
+///
+/// Handlers that want their state back after a restart log what changes it
+/// with [`Node::log`] and implement [`Handler::snapshot`], [`Handler::restore`]
+/// and [`Handler::replay`], see [`STATE_DIR`](crate::STATE_DIR).
+pub trait Handler {
+    type Body: Body;
+
+    /// Runs once after `init`, e.g. to set timers.
+    fn start(&mut self, _node: &mut Node) -> Result<()> {
+        Ok(())
+    }
+
+    fn handle(&mut self, node: &mut Node, message: Message<Self::Body>) -> Result<()>;
+
+    fn timer(&mut self, _node: &mut Node, _name: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn rpc_timeout(&mut self, _node: &mut Node, _msg_id: usize, _dest: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn shutdown(&mut self, _node: &mut Node) -> Result<()> {
+        Ok(())
+    }
+
+    /// The state to save instead of the records logged so far, `None` keeps
+    /// the log growing.
+    fn snapshot(&self) -> Result<Option<Value>> {
+        Ok(None)
+    }
+
+    /// Takes back the last [`Handler::snapshot`] after a restart, before
+    /// [`Handler::start`].
+    fn restore(&mut self, _node: &mut Node, _snapshot: Value) -> Result<()> {
+        Ok(())
+    }
+
+    /// Takes back a record logged since the last snapshot after a restart, in
+    /// order and after [`Handler::restore`]. A node that died while taking a
+    /// snapshot replays records the snapshot already has, so replaying had
+    /// better be idempotent.
+    fn replay(&mut self, _node: &mut Node, _record: Value) -> Result<()> {
+        Ok(())
+    }
+
+    /// Decodes an incoming message into `Body` for [`Handler::event`], a
+    /// [`Router`](crate::Router) takes over from here.
+    fn receive(&mut self, node: &mut Node, envelope: &Envelope<'_>) -> Result<()> {
+        let message = decode(envelope)?;
+        self.event(node, Event::Message(message))
+    }
+
+    fn event(&mut self, node: &mut Node, event: Event<Self::Body>) -> Result<()> {
+        match event {
+            Event::Message(message) => self.handle(node, message),
+            Event::Timer(name) => self.timer(node, &name),
+            Event::RpcTimeout { msg_id, dest } => self.rpc_timeout(node, msg_id, &dest),
+            Event::Shutdown => self.shutdown(node),
+        }
+    }
+}
+
+/// What the runtime threads feed the main loop.
+enum Input {
+    /// A line of stdin, parsed as far as routing it takes.
+    Line(io::Result<Incoming>),
+    /// A timer or RPC deadline is due.
+    Wake,
+    /// A worker of [`Node::run_concurrent`] failed.
+    Failed(anyhow::Error),
+    /// Stdin is closed.
+    Eof,
+}
+
+/// Work handed to a worker of [`Node::run_concurrent`].
+type Job<H> = Box<dyn FnOnce(&mut Node, &mut H) -> Result<()> + Send>;
+
+/// Where to send an `error` reply for a message that failed.
+struct Origin<'a> {
+    src: &'a str,
+    msg_id: Option<usize>,
+    /// Replies never get replies, even error ones, or two nodes could keep
+    /// bouncing errors at each other.
+    is_reply: bool,
+}
+
+impl<'a> Origin<'a> {
+    fn of(envelope: &'a Envelope<'_>) -> Self {
+        Origin {
+            src: &envelope.src,
+            msg_id: envelope.msg_id,
+            is_reply: envelope.in_reply_to.is_some(),
+        }
+    }
+}
+
+fn decode<T>(envelope: &Envelope<'_>) -> Result<Message<T>>
+where
+    T: Body,
+{
+    envelope.decode().map_err(|err| {
+        let code = match envelope.kind.as_deref() {
+            Some(kind) if !T::KINDS.contains(&kind) => ErrorCode::NotSupported,
+            _ => ErrorCode::MalformedRequest,
+        };
+        Error::new(code, err.to_string()).into()
+    })
+}
+
+/// A Maelstrom node: its identity, cluster membership and outgoing channel.
+///
+/// Clones are handles to the same node, they share message ids, stdout,
+/// timers and pending RPCs, so they can work from other threads.
+#[derive(Clone)]
+pub struct Node {
+    pub id: String,
+    pub node_ids: Vec<String>,
+    message_id: Arc<AtomicUsize>,
+    /// Message ids up to this one are reserved in the store.
+    reserved_ids: Arc<AtomicUsize>,
+    store: Option<Arc<Mutex<Store>>>,
+    out: Arc<Mutex<Box<dyn Write + Send>>>,
+    pending: Pending,
+    timers: Timers,
+    clock: Clock,
+    rpc_timeout: Duration,
+}
+
+impl Node {
+    /// Fails when the id cannot be reserved in the store, it could come up
+    /// again after a restart.
+    pub fn next_message_id(&mut self) -> Result<usize> {
+        let id = self.message_id.fetch_add(1, Ordering::Relaxed) + 1;
+        if id > self.reserved_ids.load(Ordering::Relaxed) {
+            self.reserve_ids(id)?;
+        }
+        Ok(id)
+    }
+
+    /// Reserves another block of message ids in the store, so that the node
+    /// does not use them again after a restart.
+    fn reserve_ids(&self, id: usize) -> Result<()> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+        let reserved = lock(store).reserve(id)?;
+        self.reserved_ids.fetch_max(reserved, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Appends `record` to the write-ahead log when the node keeps its state
+    /// across restarts, after one [`Handler::replay`] gets it back. Log a
+    /// change before acknowledging it.
+    pub fn log<T>(&mut self, record: &T) -> Result<()>
+    where
+        T: Serialize,
+    {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+        let record = serde_json::to_value(record).context("serializing log record")?;
+        lock(store).log(record)
+    }
+
+    /// Hands the handler what the store recovered from before a restart.
+    fn recover<H>(&mut self, handler: &mut H) -> Result<()>
+    where
+        H: Handler,
+    {
+        let Some(recovered) = self
+            .store
+            .as_ref()
+            .and_then(|store| lock(store).take_recovered())
+        else {
+            return Ok(());
+        };
+
+        let snapshot = if recovered.snapshot.is_some() {
+            "a snapshot"
+        } else {
+            "no snapshot"
+        };
+        eprintln!(
+            "Recovering {} from {snapshot} and {} log records",
+            self.id,
+            recovered.records.len()
+        );
+        if let Some(snapshot) = recovered.snapshot {
+            handler.restore(self, snapshot)?;
+        }
+        for record in recovered.records {
+            handler.replay(self, record)?;
+        }
+        Ok(())
+    }
+
+    /// Recovers the handler's state, then acknowledges `init`: Maelstrom
+    /// sends nothing else before the `init_ok`, so no request can see the
+    /// state half replayed.
+    pub(crate) fn ready<H>(&mut self, handler: &mut H, init: InitRequest) -> Result<()>
+    where
+        H: Handler,
+    {
+        self.recover(handler)?;
+        self.send(
+            &init.src,
+            InitOut::InitOK(InitOK {
+                in_reply_to: init.msg_id,
+            }),
+        )
+    }
+
+    /// Replaces the log with a snapshot once it has `at_least` records, if
+    /// the handler takes snapshots.
+    fn compact<H>(&mut self, handler: &H, at_least: usize) -> Result<()>
+    where
+        H: Handler,
+    {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+        let records = lock(store).records();
+        if records == 0 || records < at_least {
+            return Ok(());
+        }
+
+        match handler.snapshot()? {
+            Some(state) => lock(store).snapshot(state),
+            None => Ok(()),
+        }
+    }
+
+    pub fn send<T>(&mut self, dest: &str, body: T) -> Result<()>
+    where
+        T: Serialize,
+    {
+        send_message(&mut **self.out(), &self.id, dest, body).context("sending message")
+    }
+
+    fn out(&self) -> MutexGuard<'_, Box<dyn Write + Send>> {
+        self.out
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Replies to `request` with `body`, filling in its `msg_id` and
+    /// `in_reply_to`.
+    pub fn reply<B, T>(&mut self, request: &Message<B>, body: T) -> Result<()>
+    where
+        T: ReplyBody,
+    {
+        let in_reply_to = request.request_id()?;
+        self.reply_ok(&request.src, in_reply_to, body)
+    }
+
+    /// Replies to the request `in_reply_to` from `dest` with `body`, for
+    /// replies sent after the request itself is gone.
+    pub fn reply_ok<T>(&mut self, dest: &str, in_reply_to: usize, mut body: T) -> Result<()>
+    where
+        T: ReplyBody,
+    {
+        body.set_ids(self.next_message_id()?, in_reply_to);
+        self.send(dest, body)
+    }
+
+    /// Sends `body` to `dest` with `msg_id`, `in_reply_to` and, unless it has
+    /// its own, `type` filled in.
+    pub(crate) fn reply_to<T>(
+        &mut self,
+        dest: &str,
+        in_reply_to: Option<usize>,
+        kind: Option<&str>,
+        body: T,
+    ) -> Result<()>
+    where
+        T: Serialize,
+    {
+        let in_reply_to = request_id(dest, in_reply_to)?;
+        let body = RoutedReply {
+            kind,
+            msg_id: self.next_message_id()?,
+            in_reply_to,
+            body,
+        };
+        self.send(dest, body)
+    }
+
+    /// Replies to the request `in_reply_to` from `dest` with an `error`.
+    pub fn reply_error(&mut self, dest: &str, in_reply_to: usize, error: &Error) -> Result<()> {
+        let body = ErrorBody {
+            in_reply_to,
+            code: error.code,
+            text: &error.text,
+        };
+        self.send(dest, body)
+    }
+
+    /// Reports an [`Error`] back to where the failed message came from, other
+    /// errors are fatal.
+    fn fail(&mut self, origin: &Origin, err: anyhow::Error) -> Result<()> {
+        if ErrorCode::of(&err).is_none() {
+            return Err(err);
+        }
+
+        match origin.msg_id {
+            Some(msg_id) if !origin.is_reply => {
+                self.reply_error(origin.src, msg_id, &Error::from(&err))
+            }
+            _ => {
+                eprintln!("Application error: {err:#}");
+                Ok(())
+            }
+        }
+    }
+
+    /// The current time, virtual under simulation.
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// Starts the timer `name`, replacing any timer of that name. It fires
+    /// [`Event::Timer`] with the name.
+    pub fn set_timer(&mut self, name: impl Into<String>, timer: Timer) {
+        self.timers.set(name.into(), timer, self.clock.now());
+    }
+
+    /// Stops the timer `name`, returns whether it was set.
+    pub fn cancel_timer(&mut self, name: &str) -> bool {
+        self.timers.cancel(name)
+    }
+
+    pub fn set_rpc_timeout(&mut self, timeout: Duration) {
+        self.rpc_timeout = timeout;
+    }
+
+    /// Sends a request and returns a handle to its reply.
+    ///
+    /// `body` must serialize to a JSON object, the `msg_id` field is filled in
+    /// by the node and later matched against `in_reply_to` of the reply.
+    pub fn rpc<T>(&mut self, dest: &str, body: T) -> Result<Rpc>
+    where
+        T: Serialize,
+    {
+        self.rpc_with_timeout(dest, body, self.rpc_timeout)
+    }
+
+    pub fn rpc_with_timeout<T>(&mut self, dest: &str, body: T, timeout: Duration) -> Result<Rpc>
+    where
+        T: Serialize,
+    {
+        let mut body = serde_json::to_value(body).context("serializing request body")?;
+        let Some(fields) = body.as_object_mut() else {
+            bail!("request body must be a JSON object")
+        };
+
+        let msg_id = self.next_message_id()?;
+        fields.insert("msg_id".into(), msg_id.into());
+
+        let reply = self.pending.register(msg_id);
+        self.timers
+            .rpc_deadline(msg_id, dest, self.clock.now() + timeout);
+        let rpc = Rpc::new(
+            msg_id,
+            dest,
+            timeout,
+            reply,
+            self.pending.clone(),
+            self.clock.clone(),
+        );
+        self.send(dest, body)?;
+
+        Ok(rpc)
+    }
+
+    /// Builds the node from its `init` message, once it opened its store in
+    /// `state_dir` if given. [`Node::ready`] acknowledges the `init`.
+    pub(crate) fn init(
+        envelope: &Envelope<'_>,
+        out: Box<dyn Write + Send>,
+        pending: Pending,
+        timers: Timers,
+        clock: Clock,
+        state_dir: Option<&Path>,
+    ) -> Result<(Node, InitRequest)> {
+        let message = envelope
+            .decode::<InitIn>()
+            .context("expected the first message to be `init`")?;
+        let InitIn::Init(body) = message.body;
+
+        let store = state_dir
+            .map(|dir| Store::open(dir, &body.node_id))
+            .transpose()?;
+        let (first_id, reserved_ids) = match &store {
+            Some(store) => (store.first_free_id(), store.first_free_id()),
+            None => (0, usize::MAX),
+        };
+
+        let node = Node {
+            id: body.node_id,
+            node_ids: body.node_ids,
+            message_id: Arc::new(AtomicUsize::new(first_id)),
+            reserved_ids: Arc::new(AtomicUsize::new(reserved_ids)),
+            store: store.map(|store| Arc::new(Mutex::new(store))),
+            out: Arc::new(Mutex::new(out)),
+            pending,
+            timers,
+            clock,
+            rpc_timeout: DEFAULT_RPC_TIMEOUT,
+        };
+
+        let init = InitRequest {
+            src: message.src,
+            msg_id: body.msg_id,
+        };
+        Ok((node, init))
+    }
+
+    fn take_init(
+        inputs: &mpsc::Receiver<Input>,
+        pending: Pending,
+        timers: Timers,
+    ) -> Result<(Node, InitRequest)> {
+        let init = loop {
+            match inputs.recv().context("expected a message")? {
+                Input::Line(message) => break message.context("reading message")?,
+                Input::Wake => continue,
+                Input::Failed(err) => return Err(err),
+                Input::Eof => bail!("expected a message"),
+            }
+        };
+
+        let state_dir = env::var_os(STATE_DIR).map(PathBuf::from);
+        Node::init(
+            init.envelope(),
+            Box::new(io::stdout()),
+            pending,
+            timers,
+            Clock::system(),
+            state_dir.as_deref(),
+        )
+    }
+
+    /// Feeds one incoming message to the handler, replying with an `error`
+    /// when it fails with an [`Error`].
+    ///
+    /// Only the [`Envelope`] was parsed up front, the body is decoded by
+    /// [`Handler::receive`].
+    pub(crate) fn dispatch<H>(&mut self, handler: &mut H, message: &Incoming) -> Result<()>
+    where
+        H: Handler,
+    {
+        let envelope = message.envelope();
+        let origin = Origin::of(envelope);
+        if let Err(err) = handler.receive(self, envelope) {
+            self.fail(&origin, err)?;
+        }
+
+        Ok(())
+    }
+
+    /// When the next timer or RPC deadline is due.
+    pub(crate) fn next_due(&self) -> Option<Instant> {
+        self.timers.next_due()
+    }
+
+    /// The timers that are due and the RPCs that timed out without anybody
+    /// noticing.
+    fn take_due<B>(&self) -> Vec<Event<B>> {
+        let mut events = Vec::new();
+        for due in self.timers.take_due(self.clock.now()) {
+            match due {
+                Due::Timer(name) => events.push(Event::Timer(name)),
+                Due::Rpc { msg_id, dest } if self.pending.is_waiting(msg_id) => {
+                    events.push(Event::RpcTimeout { msg_id, dest })
+                }
+                Due::Rpc { .. } => {}
+            }
+        }
+        events
+    }
+
+    /// Feeds the handler what [`Node::take_due`] finds.
+    pub(crate) fn fire_due<H>(&mut self, handler: &mut H) -> Result<()>
+    where
+        H: Handler,
+    {
+        for event in self.take_due() {
+            handler.event(self, event)?;
+        }
+        Ok(())
+    }
+
+    /// Feeds the handler until input is over or something fails.
+    fn serve<H>(
+        &mut self,
+        handler: &mut H,
+        init: InitRequest,
+        inputs: &mpsc::Receiver<Input>,
+    ) -> Result<()>
+    where
+        H: Handler,
+    {
+        self.ready(handler, init)?;
+        handler.start(self)?;
+        loop {
+            match inputs.recv() {
+                Ok(Input::Wake) => self.fire_due(handler)?,
+                Ok(Input::Line(message)) => {
+                    self.dispatch(handler, &message.context("reading stdin")?)?
+                }
+                Ok(Input::Failed(err)) => return Err(err),
+                Ok(Input::Eof) | Err(_) => break,
+            }
+            self.compact(handler, SNAPSHOT_EVERY)?;
+        }
+        handler.event(self, Event::Shutdown)?;
+        self.compact(handler, 0)
+    }
+
+    /// Like [`Node::serve`], with each message and timer handled by the
+    /// first idle one of `workers` threads, each with its own clone of the
+    /// node and the handler.
+    fn serve_concurrent<H>(
+        &mut self,
+        mut handler: H,
+        init: InitRequest,
+        workers: usize,
+        inputs: &mpsc::Receiver<Input>,
+        failures: &mpsc::Sender<Input>,
+    ) -> Result<()>
+    where
+        H: Handler + Clone + Send + 'static,
+        H::Body: Send,
+    {
+        self.ready(&mut handler, init)?;
+        handler.start(self)?;
+
+        let (jobs, queue) = mpsc::channel::<Job<H>>();
+        let queue = Arc::new(Mutex::new(queue));
+        let pool: Vec<_> = (0..workers.max(1))
+            .map(|_| {
+                let queue = queue.clone();
+                let failures = failures.clone();
+                let mut node = self.clone();
+                let mut handler = handler.clone();
+                thread::spawn(move || loop {
+                    let job = queue
+                        .lock()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .recv();
+                    let Ok(job) = job else {
+                        break;
+                    };
+                    if let Err(err) = job(&mut node, &mut handler) {
+                        // the main loop stops on it, nobody to tell otherwise
+                        let _ = failures.send(Input::Failed(err));
+                        break;
+                    }
+                })
+            })
+            .collect();
+
+        let submit = |job: Job<H>| jobs.send(job).map_err(|_| anyhow!("every worker stopped"));
+        loop {
+            match inputs.recv() {
+                Ok(Input::Wake) => {
+                    for event in self.take_due() {
+                        submit(Box::new(move |node, handler| handler.event(node, event)))?;
+                    }
+                }
+                Ok(Input::Line(message)) => {
+                    let message = message.context("reading stdin")?;
+                    submit(Box::new(move |node, handler| {
+                        node.dispatch(handler, &message)
+                    }))?;
+                }
+                // the other workers may be stuck waiting on replies, they
+                // stop with the process
+                Ok(Input::Failed(err)) => return Err(err),
+                Ok(Input::Eof) | Err(_) => break,
+            }
+        }
+
+        drop(jobs);
+        for worker in pool {
+            worker.join().map_err(|_| anyhow!("a worker panicked"))?;
+        }
+        if let Some(Input::Failed(err)) = inputs
+            .try_iter()
+            .find(|input| matches!(input, Input::Failed(_)))
+        {
+            return Err(err);
+        }
+
+        // only now, with every worker done, is the state still while the
+        // snapshot is taken
+        handler.event(self, Event::Shutdown)?;
+        self.compact(&handler, 0)
+    }
+
+    /// Reads `init`, builds the handler and feeds it every following message
+    /// and timer until stdin is closed.
+    ///
+    /// Stops at the first I/O error or error from the handler that is not an
+    /// [`Error`] and returns it, the threads feeding the handler stop with it.
+    ///
+    /// With [`STATE_DIR`](crate::STATE_DIR) set the node keeps its message
+    /// ids and whatever the handler logs there, and hands them back after a
+    /// restart before it acknowledges `init`.
+    pub fn run<H, F>(make_handler: F) -> Result<()>
+    where
+        H: Handler,
+        F: FnOnce(&Node) -> H,
+    {
+        Node::try_run(|node| Ok(make_handler(node)))
+    }
+
+    /// Like [`Node::run`], for handlers that can fail to build from `init`,
+    /// which stops the node with that error.
+    pub fn try_run<H, F>(make_handler: F) -> Result<()>
+    where
+        H: Handler,
+        F: FnOnce(&Node) -> Result<H>,
+    {
+        Node::run_with(|node, init, inputs, _| {
+            let mut handler = make_handler(node)?;
+            node.serve(&mut handler, init, inputs)
+        })
+    }
+
+    /// Like [`Node::run`], with up to `workers` messages handled at once so a
+    /// handler waiting on a reply does not hold up the others.
+    ///
+    /// Every worker gets a clone of the handler, state they share has to live
+    /// behind an `Arc` and a lock. Messages may be handled in any order.
+    /// Snapshots are only taken on shutdown, the log grows until then.
+    pub fn run_concurrent<H, F>(workers: usize, make_handler: F) -> Result<()>
+    where
+        H: Handler + Clone + Send + 'static,
+        H::Body: Send,
+        F: FnOnce(&Node) -> H,
+    {
+        Node::run_with(|node, init, inputs, failures| {
+            let handler = make_handler(node);
+            node.serve_concurrent(handler, init, workers, inputs, failures)
+        })
+    }
+
+    /// Runs the threads feeding `serve` and flushes what it sent once it is
+    /// done.
+    fn run_with<S>(serve: S) -> Result<()>
+    where
+        S: FnOnce(
+            &mut Node,
+            InitRequest,
+            &mpsc::Receiver<Input>,
+            &mpsc::Sender<Input>,
+        ) -> Result<()>,
+    {
+        let (send, inputs) = mpsc::channel();
+
+        let timers = Timers::new(Rng::seed_from_time());
+        let waker_timers = timers.clone();
+        let wake = send.clone();
+        let waker = thread::spawn(move || {
+            waker_timers.run_waker(|| wake.send(Input::Wake).is_ok());
+        });
+
+        let pending = Pending::default();
+        let replies = pending.clone();
+        let reader_timers = timers.clone();
+        let lines = send.clone();
+        let reader = thread::spawn(move || {
+            for line in io::stdin().lines() {
+                let failed = line.is_err();
+                let message = match line.map(Incoming::parse) {
+                    Ok(Ok(message)) => replies.route(message).map(Ok),
+                    Ok(Err(err)) => {
+                        eprintln!("Application error: {err:#}");
+                        continue;
+                    }
+                    Err(err) => Some(Err(err)),
+                };
+                let sent = message.is_none_or(|message| lines.send(Input::Line(message)).is_ok());
+                if failed || !sent {
+                    break;
+                }
+            }
+            let _ = lines.send(Input::Eof);
+            reader_timers.stop();
+        });
+
+        let served =
+            Node::take_init(&inputs, pending, timers.clone()).and_then(|(mut node, init)| {
+                let served = serve(&mut node, init, &inputs, &send);
+                let flushed = node.out().flush().context("flushing stdout");
+                served.and(flushed)
+            });
+        // the reader stops at its next line once nobody listens, but it may be
+        // stuck reading stdin until the process exits
+        drop(inputs);
+        timers.stop();
+        waker
+            .join()
+            .map_err(|_| anyhow!("the timer thread panicked"))?;
+        if reader.is_finished() {
+            reader
+                .join()
+                .map_err(|_| anyhow!("the stdin reader panicked"))?;
+        }
+
+        served
+    }
+}
+
+fn lock(store: &Mutex<Store>) -> MutexGuard<'_, Store> {
+    store
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
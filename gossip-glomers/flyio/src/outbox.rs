@@ -0,0 +1,78 @@
+use crate::values::ValueSet;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+struct Pending {
+    values: ValueSet,
+    /// When the oldest of the values was queued.
+    since: Instant,
+}
+
+/// Values waiting to be gossiped, coalesced into one batch per destination.
+///
+/// A batch is handed back for sending once it holds `max_values` values or
+/// its oldest value has waited `max_delay`, whichever comes first. Nothing
+/// happens in the background: send what [`Outbox::add`] returns and call
+/// [`Outbox::due`] at [`Outbox::next_due`], e.g. from a one-shot timer.
+pub struct Outbox {
+    pending: BTreeMap<String, Pending>,
+    max_values: u64,
+    max_delay: Duration,
+}
+
+impl Outbox {
+    pub fn new(max_values: u64, max_delay: Duration) -> Self {
+        Outbox {
+            pending: BTreeMap::new(),
+            max_values,
+            max_delay,
+        }
+    }
+
+    /// Queues `values` for `dest`, returns the batch for `dest` if that made
+    /// it full.
+    pub fn add(&mut self, dest: &str, values: &ValueSet, now: Instant) -> Option<ValueSet> {
+        if values.is_empty() {
+            return None;
+        }
+
+        let pending = self
+            .pending
+            .entry(dest.to_string())
+            .or_insert_with(|| Pending {
+                values: ValueSet::new(),
+                since: now,
+            });
+        pending.values.union_with(values);
+
+        if pending.values.len() < self.max_values {
+            return None;
+        }
+        self.pending.remove(dest).map(|pending| pending.values)
+    }
+
+    /// Takes the batches that have waited long enough.
+    pub fn due(&mut self, now: Instant) -> Vec<(String, ValueSet)> {
+        let due: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.since + self.max_delay <= now)
+            .map(|(dest, _)| dest.clone())
+            .collect();
+
+        due.into_iter()
+            .filter_map(|dest| {
+                let pending = self.pending.remove(&dest)?;
+                Some((dest, pending.values))
+            })
+            .collect()
+    }
+
+    /// When the next batch is due, if any is waiting.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.pending
+            .values()
+            .map(|pending| pending.since + self.max_delay)
+            .min()
+    }
+}
@@ -0,0 +1,86 @@
+use crate::error::ErrorCode;
+use crate::node::Node;
+use crate::rpc::Rpc;
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::Value;
+use std::time::Duration;
+
+struct InFlight<T> {
+    dest: String,
+    payload: T,
+    rpc: Rpc,
+    backoff: Duration,
+}
+
+/// Requests that are resent with exponential backoff until the destination
+/// acknowledges them with any reply. An `error` reply means the destination
+/// will never take the request, it is given up on.
+///
+/// Nothing happens in the background: call [`Retrier::poll`] periodically,
+/// e.g. from [`crate::Handler::timer`], to collect acknowledgements and resend
+/// whatever timed out.
+pub struct Retrier<T> {
+    in_flight: Vec<InFlight<T>>,
+    initial: Duration,
+    max: Duration,
+}
+
+impl<T> Retrier<T>
+where
+    T: Serialize,
+{
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Retrier {
+            in_flight: Vec::new(),
+            initial,
+            max,
+        }
+    }
+
+    pub fn send(&mut self, node: &mut Node, dest: &str, payload: T) -> Result<()> {
+        let rpc = node.rpc_with_timeout(dest, &payload, self.initial)?;
+        self.in_flight.push(InFlight {
+            dest: dest.into(),
+            payload,
+            rpc,
+            backoff: self.initial,
+        });
+
+        Ok(())
+    }
+
+    /// Drops acknowledged requests, returning them, and resends the ones that
+    /// timed out with a doubled timeout.
+    pub fn poll(&mut self, node: &mut Node) -> Result<Vec<(String, T)>> {
+        let mut acked = Vec::new();
+        let mut waiting = Vec::with_capacity(self.in_flight.len());
+
+        for mut request in self.in_flight.drain(..) {
+            match request.rpc.try_wait::<Value>() {
+                None => waiting.push(request),
+                Some(Ok(_)) => acked.push((request.dest, request.payload)),
+                Some(Err(err)) if ErrorCode::of(&err) != Some(ErrorCode::Timeout) => {
+                    eprintln!("Application error: {err:#}");
+                }
+                Some(Err(_)) => {
+                    request.backoff = (request.backoff * 2).min(self.max);
+                    request.rpc =
+                        node.rpc_with_timeout(&request.dest, &request.payload, request.backoff)?;
+                    waiting.push(request);
+                }
+            }
+        }
+
+        self.in_flight = waiting;
+        Ok(acked)
+    }
+
+    /// Payloads sent to `dest` that are not acknowledged yet.
+    pub fn unacked<'a>(&'a self, dest: &'a str) -> impl Iterator<Item = &'a T> {
+        self.in_flight
+            .iter()
+            .filter(move |request| request.dest == dest)
+            .map(|request| &request.payload)
+    }
+}
@@ -0,0 +1,203 @@
+use crate::error::{Error, ErrorCode};
+use crate::message::{Body, Envelope, Message};
+use crate::node::{Event, Handler, Node};
+use anyhow::Result;
+use serde::de::{self, DeserializeOwned};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+type Route<H> = Arc<dyn Fn(&mut H, &mut Node, &Envelope<'_>) -> Result<()> + Send + Sync>;
+
+/// A [`Handler`] that hands each message to the function registered for its
+/// `type` with [`Router::on`], instead of matching on one big `Body`.
+///
+/// Each function gets the body decoded into its own type and a [`Reply`] to
+/// answer with. Types nothing is registered for go to the wrapped handler's
+/// own `Body` as before, so a handler can move over a type at a time; one
+/// that routes everything sets `type Body = Unrouted` and gets any other
+/// type answered with `not-supported`. Everything else, timers, shutdown
+/// and persistence, goes to the wrapped handler.
+///
+/// ```ignore
+/// Node::run(|node| {
+///     Router::new(Broadcaster::new(node))
+///         .on("broadcast", Broadcaster::handle_broadcast)
+///         .on("read", Broadcaster::handle_read)
+/// })
+/// ```
+pub struct Router<H> {
+    handler: H,
+    routes: HashMap<&'static str, Route<H>>,
+}
+
+impl<H> Router<H>
+where
+    H: Handler,
+{
+    pub fn new(handler: H) -> Self {
+        Router {
+            handler,
+            routes: HashMap::new(),
+        }
+    }
+
+    /// Routes messages of type `kind` to `route`, replacing whatever was
+    /// routed there. A body that does not decode as `T` is answered with
+    /// `malformed-request`.
+    pub fn on<T, F>(mut self, kind: &'static str, route: F) -> Self
+    where
+        T: DeserializeOwned,
+        F: Fn(&mut H, T, &mut Reply<'_>) -> Result<()> + Send + Sync + 'static,
+    {
+        let route = move |handler: &mut H, node: &mut Node, envelope: &Envelope<'_>| {
+            let body = serde_json::from_str(envelope.body.get())
+                .map_err(|err| Error::new(ErrorCode::MalformedRequest, err.to_string()))?;
+            route(handler, body, &mut Reply::to(node, envelope))
+        };
+        self.routes.insert(kind, Arc::new(route));
+        self
+    }
+
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    pub fn handler_mut(&mut self) -> &mut H {
+        &mut self.handler
+    }
+}
+
+impl<H> Clone for Router<H>
+where
+    H: Clone,
+{
+    fn clone(&self) -> Self {
+        Router {
+            handler: self.handler.clone(),
+            routes: self.routes.clone(),
+        }
+    }
+}
+
+impl<H> Handler for Router<H>
+where
+    H: Handler,
+{
+    type Body = H::Body;
+
+    fn start(&mut self, node: &mut Node) -> Result<()> {
+        self.handler.start(node)
+    }
+
+    fn handle(&mut self, node: &mut Node, message: Message<H::Body>) -> Result<()> {
+        self.handler.handle(node, message)
+    }
+
+    fn snapshot(&self) -> Result<Option<Value>> {
+        self.handler.snapshot()
+    }
+
+    fn restore(&mut self, node: &mut Node, snapshot: Value) -> Result<()> {
+        self.handler.restore(node, snapshot)
+    }
+
+    fn replay(&mut self, node: &mut Node, record: Value) -> Result<()> {
+        self.handler.replay(node, record)
+    }
+
+    fn event(&mut self, node: &mut Node, event: Event<H::Body>) -> Result<()> {
+        self.handler.event(node, event)
+    }
+
+    fn receive(&mut self, node: &mut Node, envelope: &Envelope<'_>) -> Result<()> {
+        let route = envelope
+            .kind
+            .as_deref()
+            .and_then(|kind| self.routes.get(kind));
+        match route {
+            Some(route) => route(&mut self.handler, node, envelope),
+            None => self.handler.receive(node, envelope),
+        }
+    }
+}
+
+/// How a function registered with [`Router::on`] answers its message: the
+/// node, plus where the message came from.
+pub struct Reply<'a> {
+    node: &'a mut Node,
+    src: &'a str,
+    kind: Option<&'a str>,
+    msg_id: Option<usize>,
+}
+
+impl<'a> Reply<'a> {
+    fn to(node: &'a mut Node, envelope: &'a Envelope<'_>) -> Self {
+        Reply {
+            node,
+            src: &envelope.src,
+            kind: envelope.kind.as_deref(),
+            msg_id: envelope.msg_id,
+        }
+    }
+
+    /// The node the message came from.
+    pub fn src(&self) -> &'a str {
+        self.src
+    }
+
+    pub fn node(&mut self) -> &mut Node {
+        self.node
+    }
+
+    /// Replies with `body`, which carries its own `type`, filling in
+    /// `msg_id` and `in_reply_to`.
+    pub fn send<T>(&mut self, body: T) -> Result<()>
+    where
+        T: Serialize,
+    {
+        self.node.reply_to(self.src, self.msg_id, None, body)
+    }
+
+    /// Replies with `body` as a `<type>_ok` of the message's type, `()` for
+    /// one with nothing else in it.
+    pub fn ok<T>(&mut self, body: T) -> Result<()>
+    where
+        T: Serialize,
+    {
+        let Some(kind) = self.kind else {
+            let text = format!("{} sent a message without a type", self.src);
+            return Err(Error::new(ErrorCode::MalformedRequest, text).into());
+        };
+
+        let kind = format!("{kind}_ok");
+        self.node.reply_to(self.src, self.msg_id, Some(&kind), body)
+    }
+}
+
+/// The `Body` of a handler whose messages all go through a [`Router`]: no
+/// message decodes into it, what the router does not take is answered with
+/// `not-supported`.
+#[derive(Debug)]
+pub enum Unrouted {}
+
+impl Body for Unrouted {
+    const KINDS: &'static [&'static str] = &[];
+}
+
+impl<'de> Deserialize<'de> for Unrouted {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Tagged {
+            #[serde(rename = "type")]
+            kind: String,
+        }
+
+        let Tagged { kind } = Tagged::deserialize(deserializer)?;
+        Err(de::Error::unknown_variant(&kind, &[]))
+    }
+}
@@ -0,0 +1,167 @@
+use crate::clock::Clock;
+use crate::error::{Error, ErrorCode};
+use crate::message::{Incoming, Message};
+use anyhow::{anyhow, Context, Result};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::sync::mpsc::{self, RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Replies go to their waiter as parsed by the stdin reader, their body
+/// decoded only once the caller says into what.
+type Waiters = HashMap<usize, mpsc::Sender<Incoming>>;
+
+/// Requests waiting for a reply, shared between the node and the stdin reader
+/// so that replies reach their caller even while a handler is blocked on one.
+#[derive(Clone, Default)]
+pub(crate) struct Pending(Arc<Mutex<Waiters>>);
+
+impl Pending {
+    fn waiters(&self) -> std::sync::MutexGuard<'_, Waiters> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub(crate) fn register(&self, msg_id: usize) -> mpsc::Receiver<Incoming> {
+        let (send, recv) = mpsc::channel();
+        self.waiters().insert(msg_id, send);
+        recv
+    }
+
+    pub(crate) fn is_waiting(&self, msg_id: usize) -> bool {
+        self.waiters().contains_key(&msg_id)
+    }
+
+    fn forget(&self, msg_id: usize) {
+        self.waiters().remove(&msg_id);
+    }
+
+    /// Hands the message over to the matching [`Rpc`] if it is a reply to
+    /// one, otherwise gives it back for the regular handler.
+    pub(crate) fn route(&self, message: Incoming) -> Option<Incoming> {
+        let Some(in_reply_to) = message.envelope().in_reply_to else {
+            return Some(message);
+        };
+
+        let Some(waiter) = self.waiters().remove(&in_reply_to) else {
+            return Some(message);
+        };
+
+        // the caller might have given up already, that's fine
+        let _ = waiter.send(message);
+        None
+    }
+}
+
+/// An outstanding request sent with [`crate::Node::rpc`].
+///
+/// Dropping it forgets the request, a late reply is then delivered to the
+/// handler like any other message.
+pub struct Rpc {
+    msg_id: usize,
+    dest: String,
+    deadline: Instant,
+    timeout: Duration,
+    reply: mpsc::Receiver<Incoming>,
+    pending: Pending,
+    clock: Clock,
+}
+
+impl Rpc {
+    pub(crate) fn new(
+        msg_id: usize,
+        dest: &str,
+        timeout: Duration,
+        reply: mpsc::Receiver<Incoming>,
+        pending: Pending,
+        clock: Clock,
+    ) -> Self {
+        Rpc {
+            msg_id,
+            dest: dest.into(),
+            deadline: clock.now() + timeout,
+            timeout,
+            reply,
+            pending,
+            clock,
+        }
+    }
+
+    pub fn msg_id(&self) -> usize {
+        self.msg_id
+    }
+
+    pub fn dest(&self) -> &str {
+        &self.dest
+    }
+
+    fn timed_out(&self) -> anyhow::Error {
+        let text = format!(
+            "no reply from {} to request {} within {:?}",
+            self.dest, self.msg_id, self.timeout
+        );
+        Error::new(ErrorCode::Timeout, text).into()
+    }
+
+    /// An `error` reply becomes an [`Error`].
+    fn decode<T>(&self, reply: Incoming) -> Result<Message<T>>
+    where
+        T: DeserializeOwned,
+    {
+        let envelope = reply.envelope();
+        if envelope.kind.as_deref() == Some("error") {
+            let error = serde_json::from_str::<Error>(envelope.body.get())
+                .with_context(|| format!("parsing error reply to request {}", self.msg_id))?;
+            return Err(error.into());
+        }
+
+        envelope
+            .decode()
+            .with_context(|| format!("parsing reply to request {}", self.msg_id))
+    }
+
+    /// Blocks until the reply arrives or the timeout expires.
+    ///
+    /// Under a virtual clock nothing else runs while the handler blocks, so
+    /// a reply that is not there yet times out right away.
+    pub fn wait<T>(self) -> Result<Message<T>>
+    where
+        T: DeserializeOwned,
+    {
+        if self.clock.is_virtual() {
+            return match self.reply.try_recv() {
+                Ok(reply) => self.decode(reply),
+                Err(_) => Err(self.timed_out()),
+            };
+        }
+
+        let timeout = self.deadline.saturating_duration_since(Instant::now());
+        match self.reply.recv_timeout(timeout) {
+            Ok(reply) => self.decode(reply),
+            Err(RecvTimeoutError::Timeout) => Err(self.timed_out()),
+            Err(RecvTimeoutError::Disconnected) => Err(anyhow!("node is shutting down")),
+        }
+    }
+
+    /// Checks for the reply without blocking: `None` while it is still
+    /// expected, `Some` once it arrived or the timeout expired.
+    pub fn try_wait<T>(&mut self) -> Option<Result<Message<T>>>
+    where
+        T: DeserializeOwned,
+    {
+        match self.reply.try_recv() {
+            Ok(reply) => Some(self.decode(reply)),
+            Err(TryRecvError::Empty) if self.clock.now() < self.deadline => None,
+            Err(TryRecvError::Empty) => Some(Err(self.timed_out())),
+            Err(TryRecvError::Disconnected) => Some(Err(anyhow!("node is shutting down"))),
+        }
+    }
+}
+
+impl Drop for Rpc {
+    fn drop(&mut self) {
+        self.pending.forget(self.msg_id);
+    }
+}
@@ -0,0 +1,409 @@
+//! Many nodes in one process, on a virtual clock.
+//!
+//! A [`Simulation`] feeds every node's [`Handler`] from a single event queue
+//! ordered by virtual time: deliveries after a seeded random latency and
+//! timers coming due. Nothing depends on the wall clock or thread scheduling, so
+//! the same seed replays the same run, drops and all.
+
+use crate::clock::Clock;
+use crate::message::{send_message, Envelope, Incoming};
+use crate::node::{Event, Handler, Node};
+use crate::rng::Rng;
+use crate::rpc::Pending;
+use crate::timer::Timers;
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use serde_json::json;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::io::{self, Write};
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Messages between nodes, the basis of Maelstrom's msgs-per-op.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Traffic {
+    pub messages: u64,
+    /// Messages lost to drops or partitions.
+    pub dropped: u64,
+}
+
+/// What a node prints, kept for the simulation instead of going to stdout.
+#[derive(Clone, Default)]
+struct Outbox(Arc<Mutex<Vec<u8>>>);
+
+impl Outbox {
+    fn take_lines(&self) -> Vec<String> {
+        let mut bytes = self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let bytes = mem::take(&mut *bytes);
+        String::from_utf8_lossy(&bytes)
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+}
+
+impl Write for Outbox {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut bytes = self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        bytes.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct SimNode<H> {
+    node: Node,
+    handler: H,
+    pending: Pending,
+    outbox: Outbox,
+    /// When the node is next woken up for its timers, if it is.
+    wake: Option<Instant>,
+}
+
+enum Action {
+    Deliver { dest: usize, line: String },
+    Wake { node: usize },
+}
+
+/// Actions fire by time, then in the order they were scheduled.
+struct Scheduled {
+    at: Instant,
+    seq: u64,
+    action: Action,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.seq) == (other.at, other.seq)
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.at, self.seq).cmp(&(other.at, other.seq))
+    }
+}
+
+/// A cluster of `n1`..`nN` running `H`, with a simulated network between them.
+///
+/// Whatever the nodes send outside the cluster (replies to clients, requests
+/// to services) is handed back by [`Simulation::run_until`], and
+/// [`Simulation::send`] injects messages from outside.
+pub struct Simulation<H> {
+    clock: Clock,
+    start: Instant,
+    rng: Rng,
+    node_ids: Vec<String>,
+    index: HashMap<String, usize>,
+    nodes: Vec<SimNode<H>>,
+    queue: BinaryHeap<Reverse<Scheduled>>,
+    seq: u64,
+    latency: Duration,
+    jitter: Duration,
+    drop: f64,
+    /// One side of the current partition, nodes on different sides cannot
+    /// talk to each other.
+    partition: Option<HashSet<String>>,
+    outside: Vec<String>,
+    traffic: Traffic,
+}
+
+impl<H> Simulation<H>
+where
+    H: Handler,
+{
+    /// Initializes `node_count` nodes and builds their handlers.
+    pub fn new<F>(seed: u64, node_count: usize, mut make_handler: F) -> Result<Self>
+    where
+        F: FnMut(&Node) -> H,
+    {
+        let start = Instant::now();
+        let clock = Clock::virtual_from(start);
+        let mut rng = Rng::new(seed);
+        let node_ids: Vec<String> = (1..=node_count).map(|i| format!("n{i}")).collect();
+
+        let mut nodes = Vec::with_capacity(node_count);
+        for id in &node_ids {
+            let init = json!({
+                "src": "sim",
+                "dest": id,
+                "body": {"type": "init", "msg_id": 0, "node_id": id, "node_ids": node_ids},
+            });
+            let outbox = Outbox::default();
+            let pending = Pending::default();
+            let init = init.to_string();
+            let (mut node, init) = Node::init(
+                &Envelope::parse(&init)?,
+                Box::new(outbox.clone()),
+                pending.clone(),
+                Timers::new(rng.next_u64()),
+                clock.clone(),
+                None,
+            )?;
+            let mut handler = make_handler(&node);
+            node.ready(&mut handler, init)?;
+            // the init_ok
+            outbox.take_lines();
+
+            nodes.push(SimNode {
+                node,
+                handler,
+                pending,
+                outbox,
+                wake: None,
+            });
+        }
+
+        let mut sim = Simulation {
+            clock,
+            start,
+            rng,
+            index: node_ids
+                .iter()
+                .enumerate()
+                .map(|(i, id)| (id.clone(), i))
+                .collect(),
+            node_ids,
+            nodes,
+            queue: BinaryHeap::new(),
+            seq: 0,
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            drop: 0.0,
+            partition: None,
+            outside: Vec::new(),
+            traffic: Traffic::default(),
+        };
+
+        for index in 0..node_count {
+            let sim_node = &mut sim.nodes[index];
+            let result = sim_node.handler.start(&mut sim_node.node);
+            sim.then(index, result)?;
+        }
+
+        Ok(sim)
+    }
+
+    pub fn node_ids(&self) -> &[String] {
+        &self.node_ids
+    }
+
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// Virtual time since the simulation started.
+    pub fn elapsed(&self) -> Duration {
+        self.now() - self.start
+    }
+
+    /// The handler of node `id`, to look at its state.
+    pub fn handler(&self, id: &str) -> Option<&H> {
+        self.index.get(id).map(|&i| &self.nodes[i].handler)
+    }
+
+    /// Every message takes `latency` plus up to `jitter`, picked at random.
+    pub fn set_latency(&mut self, latency: Duration, jitter: Duration) {
+        self.latency = latency;
+        self.jitter = jitter;
+    }
+
+    /// Probability of losing a message between nodes.
+    pub fn set_drop(&mut self, drop: f64) {
+        self.drop = drop;
+    }
+
+    pub fn partition<I>(&mut self, side: I)
+    where
+        I: IntoIterator<Item = String>,
+    {
+        self.partition = Some(side.into_iter().collect());
+    }
+
+    pub fn heal(&mut self) {
+        self.partition = None;
+    }
+
+    pub fn is_partitioned(&self) -> bool {
+        self.partition.is_some()
+    }
+
+    pub fn traffic(&self) -> Traffic {
+        self.traffic
+    }
+
+    /// Sends a message from outside the cluster, it is delayed like any other
+    /// but never lost.
+    pub fn send<T>(&mut self, src: &str, dest: &str, body: T) -> Result<()>
+    where
+        T: Serialize,
+    {
+        let mut line = Vec::new();
+        send_message(&mut line, src, dest, body)?;
+        let line = String::from_utf8(line).context("message is not UTF-8")?;
+        self.send_line(dest, line.trim_end().into())
+    }
+
+    /// Like [`Simulation::send`] with an already serialized message.
+    pub fn send_line(&mut self, dest: &str, line: String) -> Result<()> {
+        let Some(&dest) = self.index.get(dest) else {
+            bail!("no node {dest} in the simulation");
+        };
+
+        let at = self.now() + self.delay();
+        self.schedule(at, Action::Deliver { dest, line });
+        Ok(())
+    }
+
+    /// Tells every node input is over, what they send then is lost.
+    pub fn shutdown(&mut self) -> Result<()> {
+        for index in 0..self.nodes.len() {
+            let sim = &mut self.nodes[index];
+            let result = sim.handler.event(&mut sim.node, Event::Shutdown);
+            sim.outbox.take_lines();
+            self.check(index, result)?;
+        }
+        Ok(())
+    }
+
+    /// When the next event fires, if any is left.
+    pub fn next_event(&self) -> Option<Instant> {
+        self.queue.peek().map(|Reverse(scheduled)| scheduled.at)
+    }
+
+    /// Fires every event due until `until`, stopping early as soon as the
+    /// nodes addressed something outside the cluster, and returns those
+    /// messages as lines.
+    pub fn run_until(&mut self, until: Instant) -> Result<Vec<String>> {
+        while self.outside.is_empty() {
+            let Some(at) = self.next_event().filter(|at| *at <= until) else {
+                self.clock.advance_to(until);
+                break;
+            };
+
+            let Some(Reverse(scheduled)) = self.queue.pop() else {
+                break;
+            };
+            self.clock.advance_to(at);
+            self.fire(scheduled.action)?;
+        }
+
+        Ok(mem::take(&mut self.outside))
+    }
+
+    /// [`Simulation::run_until`] `duration` from now.
+    pub fn run_for(&mut self, duration: Duration) -> Result<Vec<String>> {
+        self.run_until(self.now() + duration)
+    }
+
+    fn delay(&mut self) -> Duration {
+        self.latency + self.jitter.mul_f64(self.rng.unit())
+    }
+
+    fn schedule(&mut self, at: Instant, action: Action) {
+        self.seq += 1;
+        self.queue.push(Reverse(Scheduled {
+            at,
+            seq: self.seq,
+            action,
+        }));
+    }
+
+    fn fire(&mut self, action: Action) -> Result<()> {
+        match action {
+            Action::Deliver { dest, line } => {
+                let sim = &mut self.nodes[dest];
+                let message = match Incoming::parse(line) {
+                    Ok(message) => message,
+                    Err(err) => {
+                        eprintln!("Application error: {err:#}");
+                        return Ok(());
+                    }
+                };
+                let result = match sim.pending.route(message) {
+                    Some(message) => sim.node.dispatch(&mut sim.handler, &message),
+                    None => Ok(()),
+                };
+                self.then(dest, result)
+            }
+            Action::Wake { node } => {
+                let now = self.now();
+                let sim = &mut self.nodes[node];
+                if sim.wake != Some(now) {
+                    // superseded by an earlier wake-up
+                    return Ok(());
+                }
+                sim.wake = None;
+                let result = sim.node.fire_due(&mut sim.handler);
+                self.then(node, result)
+            }
+        }
+    }
+
+    fn check(&self, index: usize, result: Result<()>) -> Result<()> {
+        result
+            .with_context(|| format!("{} failed after {:?}", self.node_ids[index], self.elapsed()))
+    }
+
+    /// Follows up on node `index` having run: sends what it printed and wakes
+    /// it up again when its next timer is due.
+    fn then(&mut self, index: usize, result: Result<()>) -> Result<()> {
+        self.check(index, result)?;
+
+        for line in self.nodes[index].outbox.take_lines() {
+            self.route(index, line)?;
+        }
+
+        let now = self.now();
+        let sim = &mut self.nodes[index];
+        if let Some(at) = sim.node.next_due().map(|at| at.max(now)) {
+            if sim.wake.is_none_or(|wake| at < wake) {
+                sim.wake = Some(at);
+                self.schedule(at, Action::Wake { node: index });
+            }
+        }
+        Ok(())
+    }
+
+    fn route(&mut self, src: usize, line: String) -> Result<()> {
+        let envelope = Envelope::parse(&line)
+            .with_context(|| format!("{} sent a malformed message: {line}", self.node_ids[src]))?;
+
+        let Some(&dest) = self.index.get(&*envelope.dest) else {
+            self.outside.push(line);
+            return Ok(());
+        };
+
+        self.traffic.messages += 1;
+        let split = self.partition.as_ref().is_some_and(|side| {
+            side.contains(&self.node_ids[src]) != side.contains(&self.node_ids[dest])
+        });
+        if split || self.rng.chance(self.drop) {
+            self.traffic.dropped += 1;
+            return Ok(());
+        }
+
+        let at = self.now() + self.delay();
+        self.schedule(at, Action::Deliver { dest, line });
+        Ok(())
+    }
+}
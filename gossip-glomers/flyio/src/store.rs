@@ -0,0 +1,195 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// Env var naming the directory nodes keep their state in across restarts,
+/// nothing is persisted without it.
+pub const STATE_DIR: &str = "STATE_DIR";
+
+/// Message ids reserved in the log at a time, so that the log only grows by
+/// a line every that many messages.
+const ID_BLOCK: usize = 1000;
+
+/// A line of the write-ahead log.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Entry {
+    /// Message ids up to this one may have been used.
+    Ids(usize),
+    /// Something the handler logged with [`crate::Node::log`].
+    Record(Value),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    ids: usize,
+    state: Value,
+}
+
+/// What a node left behind before it restarted, for the handler to replay.
+pub(crate) struct Recovered {
+    pub(crate) snapshot: Option<Value>,
+    pub(crate) records: Vec<Value>,
+}
+
+/// The write-ahead log and snapshot of one node, `<node>.wal` and
+/// `<node>.snapshot` in the state directory.
+///
+/// Every entry is written with a single `write` of its line and synced to
+/// disk before it returns, so it survives the process being killed or the
+/// machine going down right after; a line torn by a crash in the middle of
+/// that is ignored on replay. A snapshot replaces the log up to it and is
+/// written and synced to a temporary file first, then renamed over the
+/// previous one, and the directory is synced so the rename sticks too.
+pub(crate) struct Store {
+    dir: PathBuf,
+    wal_path: PathBuf,
+    snapshot_path: PathBuf,
+    wal: File,
+    /// Records logged since the last snapshot.
+    records: usize,
+    /// Message ids up to this one are reserved in the log.
+    reserved: usize,
+    recovered: Option<Recovered>,
+}
+
+impl Store {
+    /// Opens the state of `node_id` in `dir`, reading back whatever an
+    /// earlier run of it left there.
+    pub(crate) fn open(dir: &Path, node_id: &str) -> Result<Store> {
+        fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        let wal_path = dir.join(format!("{node_id}.wal"));
+        let snapshot_path = dir.join(format!("{node_id}.snapshot"));
+
+        let mut reserved = 0;
+        let mut snapshot = None;
+        match fs::read(&snapshot_path) {
+            Ok(bytes) => {
+                let saved: Snapshot = serde_json::from_slice(&bytes)
+                    .with_context(|| format!("reading {}", snapshot_path.display()))?;
+                reserved = saved.ids;
+                snapshot = Some(saved.state);
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => {
+                return Err(err).with_context(|| format!("reading {}", snapshot_path.display()))
+            }
+        }
+
+        let mut records = Vec::new();
+        match File::open(&wal_path) {
+            Ok(wal) => {
+                for line in BufReader::new(wal).lines() {
+                    let line = line.with_context(|| format!("reading {}", wal_path.display()))?;
+                    match serde_json::from_str(&line) {
+                        Ok(Entry::Ids(ids)) => reserved = reserved.max(ids),
+                        Ok(Entry::Record(record)) => records.push(record),
+                        Err(err) => {
+                            eprintln!("Skipping a torn line of {}: {err}", wal_path.display());
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err).with_context(|| format!("reading {}", wal_path.display())),
+        }
+
+        let wal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&wal_path)
+            .with_context(|| format!("opening {}", wal_path.display()))?;
+        sync_dir(dir)?;
+
+        let logged = records.len();
+        let recovered =
+            (snapshot.is_some() || !records.is_empty()).then_some(Recovered { snapshot, records });
+        Ok(Store {
+            dir: dir.into(),
+            wal_path,
+            snapshot_path,
+            wal,
+            records: logged,
+            reserved,
+            recovered,
+        })
+    }
+
+    fn append(&mut self, entry: &Entry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry).context("serializing log entry")?;
+        line.push(b'\n');
+        self.wal
+            .write_all(&line)
+            .and_then(|()| self.wal.sync_data())
+            .with_context(|| format!("writing {}", self.wal_path.display()))
+    }
+
+    pub(crate) fn log(&mut self, record: Value) -> Result<()> {
+        self.append(&Entry::Record(record))?;
+        self.records += 1;
+        Ok(())
+    }
+
+    /// The first message id that is safe to use, none before it may have
+    /// been.
+    pub(crate) fn first_free_id(&self) -> usize {
+        self.reserved
+    }
+
+    /// Makes sure ids up to `id` are reserved, returns up to which they are.
+    pub(crate) fn reserve(&mut self, id: usize) -> Result<usize> {
+        if id > self.reserved {
+            let reserved = id + ID_BLOCK;
+            self.append(&Entry::Ids(reserved))?;
+            self.reserved = reserved;
+        }
+        Ok(self.reserved)
+    }
+
+    pub(crate) fn take_recovered(&mut self) -> Option<Recovered> {
+        self.recovered.take()
+    }
+
+    /// Records logged since the last snapshot.
+    pub(crate) fn records(&self) -> usize {
+        self.records
+    }
+
+    /// Replaces the log with `state`.
+    pub(crate) fn snapshot(&mut self, state: Value) -> Result<()> {
+        let snapshot = Snapshot {
+            ids: self.reserved,
+            state,
+        };
+        let bytes = serde_json::to_vec(&snapshot).context("serializing snapshot")?;
+
+        let tmp = self.snapshot_path.with_extension("snapshot.tmp");
+        File::create(&tmp)
+            .and_then(|mut file| {
+                file.write_all(&bytes)?;
+                file.sync_all()
+            })
+            .with_context(|| format!("writing {}", tmp.display()))?;
+        fs::rename(&tmp, &self.snapshot_path)
+            .with_context(|| format!("writing {}", self.snapshot_path.display()))?;
+        sync_dir(&self.dir)?;
+
+        // a truncation lost to a crash only replays records the snapshot has
+        self.wal = File::create(&self.wal_path)
+            .with_context(|| format!("truncating {}", self.wal_path.display()))?;
+        self.records = 0;
+        Ok(())
+    }
+}
+
+/// Syncs the entries of `dir`, so that files created or renamed in it are
+/// there after a crash of the machine.
+fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .with_context(|| format!("syncing {}", dir.display()))
+}
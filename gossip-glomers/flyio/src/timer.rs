@@ -0,0 +1,251 @@
+use crate::rng::Rng;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// When a named timer set with [`crate::Node::set_timer`] fires.
+#[derive(Clone, Copy, Debug)]
+pub struct Timer {
+    after: Duration,
+    period: Option<Duration>,
+    jitter: Duration,
+}
+
+impl Timer {
+    /// Fires once, `after` from now.
+    pub fn once(after: Duration) -> Self {
+        Timer {
+            after,
+            period: None,
+            jitter: Duration::ZERO,
+        }
+    }
+
+    /// Fires every `period`, the first time one period from now.
+    pub fn every(period: Duration) -> Self {
+        Timer {
+            after: period,
+            period: Some(period),
+            jitter: Duration::ZERO,
+        }
+    }
+
+    /// Delays every firing by up to `jitter`, picked at random, so that nodes
+    /// started together do not all fire at once.
+    pub fn with_jitter(self, jitter: Duration) -> Self {
+        Timer { jitter, ..self }
+    }
+}
+
+/// Something in the schedule that came due.
+pub(crate) enum Due {
+    Timer(String),
+    Rpc { msg_id: usize, dest: String },
+}
+
+enum Key {
+    Timer { name: String, id: u64 },
+    Rpc { msg_id: usize, dest: String },
+}
+
+/// Timers and RPC deadlines of one node, in the order they come due.
+///
+/// Cancelled or replaced timers stay in the queue and are skipped when they
+/// come up, which keeps cancelling cheap.
+struct Schedule {
+    /// The current generation and definition of every live timer, setting a
+    /// timer again starts a new generation.
+    timers: HashMap<String, (u64, Timer)>,
+    generation: u64,
+    queue: BinaryHeap<Reverse<(Instant, u64)>>,
+    keys: HashMap<u64, Key>,
+    seq: u64,
+    rng: Rng,
+}
+
+impl Schedule {
+    fn push(&mut self, at: Instant, key: Key) {
+        self.seq += 1;
+        self.queue.push(Reverse((at, self.seq)));
+        self.keys.insert(self.seq, key);
+    }
+
+    fn jittered(&mut self, delay: Duration, jitter: Duration) -> Duration {
+        delay + jitter.mul_f64(self.rng.unit())
+    }
+
+    fn set(&mut self, name: String, timer: Timer, now: Instant) {
+        self.generation += 1;
+        let at = now + self.jittered(timer.after, timer.jitter);
+        let key = Key::Timer {
+            name: name.clone(),
+            id: self.generation,
+        };
+        self.push(at, key);
+        self.timers.insert(name, (self.generation, timer));
+    }
+
+    fn take_due(&mut self, now: Instant) -> Vec<Due> {
+        let mut due = Vec::new();
+
+        while let Some(&Reverse((at, seq))) = self.queue.peek() {
+            if at > now {
+                break;
+            }
+            self.queue.pop();
+
+            match self.keys.remove(&seq) {
+                Some(Key::Timer { name, id }) => {
+                    let Some(&(current, timer)) = self.timers.get(&name) else {
+                        continue;
+                    };
+                    if current != id {
+                        continue;
+                    }
+
+                    match timer.period {
+                        Some(period) => {
+                            let next = at + self.jittered(period, timer.jitter);
+                            let key = Key::Timer {
+                                name: name.clone(),
+                                id,
+                            };
+                            self.push(next.max(now), key);
+                        }
+                        None => {
+                            self.timers.remove(&name);
+                        }
+                    }
+                    due.push(Due::Timer(name));
+                }
+                Some(Key::Rpc { msg_id, dest }) => due.push(Due::Rpc { msg_id, dest }),
+                None => {}
+            }
+        }
+
+        due
+    }
+}
+
+struct State {
+    schedule: Schedule,
+    /// The runtime was told something is due and did not collect it yet.
+    woken: bool,
+    stopped: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    /// Signalled on every change to the schedule.
+    changed: Condvar,
+}
+
+/// The [`Schedule`] shared between a node and the thread waking it up.
+#[derive(Clone)]
+pub(crate) struct Timers(Arc<Shared>);
+
+impl Timers {
+    pub(crate) fn new(seed: u64) -> Self {
+        let schedule = Schedule {
+            timers: HashMap::new(),
+            generation: 0,
+            queue: BinaryHeap::new(),
+            keys: HashMap::new(),
+            seq: 0,
+            rng: Rng::new(seed),
+        };
+
+        Timers(Arc::new(Shared {
+            state: Mutex::new(State {
+                schedule,
+                woken: false,
+                stopped: false,
+            }),
+            changed: Condvar::new(),
+        }))
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.0
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Runs `change` on the schedule and lets the waker know.
+    fn update<T>(&self, change: impl FnOnce(&mut Schedule) -> T) -> T {
+        let result = change(&mut self.state().schedule);
+        self.0.changed.notify_all();
+        result
+    }
+
+    pub(crate) fn set(&self, name: String, timer: Timer, now: Instant) {
+        self.update(|schedule| schedule.set(name, timer, now));
+    }
+
+    pub(crate) fn cancel(&self, name: &str) -> bool {
+        self.update(|schedule| schedule.timers.remove(name).is_some())
+    }
+
+    pub(crate) fn rpc_deadline(&self, msg_id: usize, dest: &str, at: Instant) {
+        let key = Key::Rpc {
+            msg_id,
+            dest: dest.into(),
+        };
+        self.update(|schedule| schedule.push(at, key));
+    }
+
+    /// When the earliest entry comes due, it might turn out to be cancelled.
+    pub(crate) fn next_due(&self) -> Option<Instant> {
+        let state = self.state();
+        state.schedule.queue.peek().map(|Reverse((at, _))| *at)
+    }
+
+    /// Collects what is due, which also answers the last wake-up.
+    pub(crate) fn take_due(&self, now: Instant) -> Vec<Due> {
+        let due = {
+            let mut state = self.state();
+            state.woken = false;
+            state.schedule.take_due(now)
+        };
+        self.0.changed.notify_all();
+        due
+    }
+
+    pub(crate) fn stop(&self) {
+        self.state().stopped = true;
+        self.0.changed.notify_all();
+    }
+
+    /// Calls `wake` whenever something comes due, until [`Timers::stop`] or
+    /// until `wake` returns `false`. Meant for a thread of its own.
+    pub(crate) fn run_waker(&self, mut wake: impl FnMut() -> bool) {
+        let changed = &self.0.changed;
+        let mut state = self.state();
+
+        while !state.stopped {
+            let next = state.schedule.queue.peek().map(|Reverse((at, _))| *at);
+            let now = Instant::now();
+
+            state = match next {
+                Some(at) if !state.woken && at <= now => {
+                    state.woken = true;
+                    if !wake() {
+                        return;
+                    }
+                    state
+                }
+                Some(at) if !state.woken => {
+                    changed
+                        .wait_timeout(state, at - now)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .0
+                }
+                _ => changed
+                    .wait(state)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()),
+            };
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use anyhow::{bail, Context, Result};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::str::FromStr;
+
+/// The neighbour map from Maelstrom's `topology` message.
+pub type Grid = HashMap<String, Vec<String>>;
+
+/// How a node picks the peers it gossips with.
+///
+/// Parsed from strings like `grid`, `spanning-tree`, `star:2` or `tree:4`,
+/// see [`Topology::from_env`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Topology {
+    /// Maelstrom's own neighbour map, as is.
+    Grid,
+    /// A BFS tree over Maelstrom's map rooted at the first node: same
+    /// reachability with a single path between any two nodes.
+    SpanningTree,
+    /// The first `hubs` nodes are all connected with each other and every
+    /// other node hangs off one of them.
+    Star { hubs: usize },
+    /// A complete `arity`-ary tree laid out over the node ids in order.
+    Tree { arity: usize },
+}
+
+impl Default for Topology {
+    fn default() -> Self {
+        Topology::Star { hubs: 1 }
+    }
+}
+
+impl Topology {
+    /// Env var consulted by [`Topology::from_env`].
+    pub const ENV: &'static str = "TOPOLOGY";
+
+    /// Reads the strategy from the `TOPOLOGY` env var, falling back to the
+    /// default when it is not set.
+    pub fn from_env() -> Result<Topology> {
+        match std::env::var(Self::ENV) {
+            Ok(spec) => spec.parse(),
+            Err(std::env::VarError::NotPresent) => Ok(Topology::default()),
+            Err(err) => Err(err).context(Self::ENV),
+        }
+    }
+
+    /// Peers `node_id` exchanges messages with, `grid` is empty until the
+    /// `topology` message arrives.
+    pub fn neighbours(&self, node_id: &str, node_ids: &[String], grid: &Grid) -> Vec<String> {
+        match *self {
+            Topology::Grid => grid.get(node_id).cloned().unwrap_or_default(),
+            Topology::SpanningTree => spanning_tree(node_id, node_ids, grid),
+            Topology::Star { hubs } => star(node_id, node_ids, hubs),
+            Topology::Tree { arity } => tree(node_id, node_ids, arity),
+        }
+    }
+}
+
+fn spanning_tree(node_id: &str, node_ids: &[String], grid: &Grid) -> Vec<String> {
+    let Some(root) = node_ids.first() else {
+        return Vec::new();
+    };
+
+    let mut visited = HashSet::from([root.as_str()]);
+    let mut queue = VecDeque::from([root.as_str()]);
+    let mut neighbours = Vec::new();
+
+    while let Some(parent) = queue.pop_front() {
+        let Some(adjacent) = grid.get(parent) else {
+            continue;
+        };
+
+        // sorted, so every node builds exactly the same tree
+        let mut adjacent: Vec<&str> = adjacent.iter().map(String::as_str).collect();
+        adjacent.sort_unstable();
+
+        for child in adjacent {
+            if !visited.insert(child) {
+                continue;
+            }
+            if parent == node_id {
+                neighbours.push(child.to_string());
+            } else if child == node_id {
+                neighbours.push(parent.to_string());
+            }
+            queue.push_back(child);
+        }
+    }
+
+    neighbours
+}
+
+fn star(node_id: &str, node_ids: &[String], hubs: usize) -> Vec<String> {
+    let hubs = hubs.clamp(1, node_ids.len().max(1));
+    let Some(index) = node_ids.iter().position(|id| id == node_id) else {
+        return Vec::new();
+    };
+
+    if index < hubs {
+        node_ids
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != index && (i < hubs || i % hubs == index))
+            .map(|(_, id)| id.clone())
+            .collect()
+    } else {
+        vec![node_ids[index % hubs].clone()]
+    }
+}
+
+fn tree(node_id: &str, node_ids: &[String], arity: usize) -> Vec<String> {
+    let arity = arity.max(1);
+    let Some(index) = node_ids.iter().position(|id| id == node_id) else {
+        return Vec::new();
+    };
+
+    let parent = index.checked_sub(1).map(|i| i / arity);
+    let children = (arity * index + 1)..(arity * index + arity + 1);
+
+    parent
+        .into_iter()
+        .chain(children)
+        .filter_map(|i| node_ids.get(i))
+        .cloned()
+        .collect()
+}
+
+impl FromStr for Topology {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let (name, param) = match spec.split_once(':') {
+            Some((name, param)) => {
+                let param = param
+                    .parse::<usize>()
+                    .with_context(|| format!("parsing topology parameter in {spec:?}"))?;
+                (name, Some(param))
+            }
+            None => (spec, None),
+        };
+
+        Ok(match (name, param) {
+            ("grid", None) => Topology::Grid,
+            ("spanning-tree", None) => Topology::SpanningTree,
+            ("star", hubs) => Topology::Star {
+                hubs: hubs.unwrap_or(1),
+            },
+            ("tree", arity) => Topology::Tree {
+                arity: arity.unwrap_or(4),
+            },
+            _ => bail!("unknown topology {spec:?}, expected grid, spanning-tree, star[:hubs] or tree[:arity]"),
+        })
+    }
+}
@@ -0,0 +1,284 @@
+use crate::digest::bucket_values;
+use serde::de::{self, Deserializer};
+use serde::ser::{SerializeSeq, Serializer};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+/// A set of broadcast values stored as disjoint runs of consecutive values.
+///
+/// Workloads hand out values from a counter, so tens of thousands of them
+/// collapse to a handful of runs. Clones share the runs until one of them
+/// changes, which makes snapshots for gossip or `read_ok` cheap.
+///
+/// Serializes as its runs, a value on its own or `[first, last]`, e.g.
+/// `[1, [3, 9], 12]`. Use [`ValueSet::flat`] where the protocol wants every
+/// value listed.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct ValueSet {
+    /// First value of every run to its last.
+    runs: Arc<BTreeMap<i32, i32>>,
+    len: u64,
+}
+
+impl ValueSet {
+    pub fn new() -> Self {
+        ValueSet::default()
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains(&self, value: i32) -> bool {
+        self.runs
+            .range(..=value)
+            .next_back()
+            .is_some_and(|(_, &last)| last >= value)
+    }
+
+    /// Adds `value`, returns whether it was new.
+    pub fn insert(&mut self, value: i32) -> bool {
+        if self.contains(value) {
+            return false;
+        }
+        self.insert_run(value, value);
+        true
+    }
+
+    /// Adds every value from `first` to `last`.
+    pub fn insert_run(&mut self, first: i32, last: i32) {
+        if first > last {
+            return;
+        }
+        let runs = Arc::make_mut(&mut self.runs);
+
+        let (mut start, mut end) = (first, last);
+        if let Some((&before, &before_end)) = runs.range(..first).next_back() {
+            if i64::from(before_end) + 1 >= i64::from(first) {
+                start = before;
+            }
+        }
+
+        // every run overlapping or touching the new one gets merged into it
+        let reach = i64::from(last) + 1;
+        let merged: Vec<i32> = runs
+            .range(start..)
+            .take_while(|(&run, _)| i64::from(run) <= reach)
+            .map(|(&run, _)| run)
+            .collect();
+        for run in merged {
+            if let Some(run_end) = runs.remove(&run) {
+                self.len -= run_len(run, run_end);
+                end = end.max(run_end);
+            }
+        }
+
+        runs.insert(start, end);
+        self.len += run_len(start, end);
+    }
+
+    /// Adds every value of `other`.
+    pub fn union_with(&mut self, other: &ValueSet) {
+        if self.is_empty() {
+            *self = other.clone();
+            return;
+        }
+        for run in other.runs() {
+            self.insert_run(*run.start(), *run.end());
+        }
+    }
+
+    pub fn union(&self, other: &ValueSet) -> ValueSet {
+        let (mut union, smaller) = if self.runs.len() >= other.runs.len() {
+            (self.clone(), other)
+        } else {
+            (other.clone(), self)
+        };
+        union.union_with(smaller);
+        union
+    }
+
+    /// The values of `self` missing from `other`.
+    pub fn difference(&self, other: &ValueSet) -> ValueSet {
+        if other.is_empty() {
+            return self.clone();
+        }
+
+        let mut difference = ValueSet::new();
+        let runs = Arc::make_mut(&mut difference.runs);
+        let mut push = |first: i64, last: i64| {
+            runs.insert(first as i32, last as i32);
+            difference.len += (last - first + 1) as u64;
+        };
+
+        for (&first, &last) in self.runs.iter() {
+            let mut next = i64::from(first);
+            let before = other.runs.range(..first).next_back();
+            for (&hole, &hole_end) in before.into_iter().chain(other.runs.range(first..=last)) {
+                if i64::from(hole_end) < next {
+                    continue;
+                }
+                if i64::from(hole) > next {
+                    push(next, i64::from(hole) - 1);
+                }
+                next = i64::from(hole_end) + 1;
+            }
+            if next <= i64::from(last) {
+                push(next, i64::from(last));
+            }
+        }
+
+        difference
+    }
+
+    /// The values of `self` also in `other`.
+    pub fn intersection(&self, other: &ValueSet) -> ValueSet {
+        self.difference(&self.difference(other))
+    }
+
+    /// The values from `first` to `last`.
+    pub fn within(&self, first: i32, last: i32) -> ValueSet {
+        let mut within = ValueSet::new();
+        if first > last {
+            return within;
+        }
+
+        let before = self.runs.range(..first).next_back();
+        for (&start, &end) in before.into_iter().chain(self.runs.range(first..=last)) {
+            if end >= first {
+                within.insert_run(start.max(first), end.min(last));
+            }
+        }
+        within
+    }
+
+    /// The values falling in any of `buckets` of a [`Digest`](crate::Digest).
+    pub fn in_buckets(&self, buckets: &[i64]) -> ValueSet {
+        let mut values = ValueSet::new();
+        for range in buckets.iter().filter_map(|&bucket| bucket_values(bucket)) {
+            values.union_with(&self.within(*range.start(), *range.end()));
+        }
+        values
+    }
+
+    /// The runs in ascending order.
+    pub fn runs(&self) -> impl Iterator<Item = RangeInclusive<i32>> + '_ {
+        self.runs.iter().map(|(&first, &last)| first..=last)
+    }
+
+    /// Every value in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = i32> + '_ {
+        self.runs().flat_map(|run| run.into_iter())
+    }
+
+    /// Serializes as the plain list of values.
+    pub fn flat(&self) -> FlatValues<'_> {
+        FlatValues(self)
+    }
+}
+
+fn run_len(first: i32, last: i32) -> u64 {
+    (i64::from(last) - i64::from(first) + 1) as u64
+}
+
+impl fmt::Debug for ValueSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.runs()).finish()
+    }
+}
+
+impl Extend<i32> for ValueSet {
+    fn extend<I>(&mut self, values: I)
+    where
+        I: IntoIterator<Item = i32>,
+    {
+        for value in values {
+            self.insert(value);
+        }
+    }
+}
+
+impl<'a> Extend<&'a i32> for ValueSet {
+    fn extend<I>(&mut self, values: I)
+    where
+        I: IntoIterator<Item = &'a i32>,
+    {
+        self.extend(values.into_iter().copied());
+    }
+}
+
+impl FromIterator<i32> for ValueSet {
+    fn from_iter<I>(values: I) -> Self
+    where
+        I: IntoIterator<Item = i32>,
+    {
+        let mut set = ValueSet::new();
+        set.extend(values);
+        set
+    }
+}
+
+/// A run as it goes over the wire.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Run {
+    One(i32),
+    Many(i32, i32),
+}
+
+impl Serialize for ValueSet {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut seq = serializer.serialize_seq(Some(self.runs.len()))?;
+        for (&first, &last) in self.runs.iter() {
+            if first == last {
+                seq.serialize_element(&Run::One(first))?;
+            } else {
+                seq.serialize_element(&Run::Many(first, last))?;
+            }
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for ValueSet {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut set = ValueSet::new();
+        for run in Vec::<Run>::deserialize(deserializer)? {
+            match run {
+                Run::One(value) => set.insert_run(value, value),
+                Run::Many(first, last) if first <= last => set.insert_run(first, last),
+                Run::Many(first, last) => {
+                    return Err(de::Error::custom(format!("empty run [{first}, {last}]")))
+                }
+            }
+        }
+        Ok(set)
+    }
+}
+
+/// A [`ValueSet`] serialized as the plain list of its values, like `read_ok`
+/// wants them.
+#[derive(Debug)]
+pub struct FlatValues<'a>(&'a ValueSet);
+
+impl Serialize for FlatValues<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(self.0.iter())
+    }
+}
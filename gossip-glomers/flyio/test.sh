@@ -0,0 +1,100 @@
+#!/usr/bin/env bash
+
+set -e
+
+cargo build --quiet --package flyio --example values
+failed=0
+
+# Runs one ValueSet or Digest operation and compares its result with the one expected.
+check() {
+  local actual
+  actual=$(echo "$1" | ../target/debug/examples/values)
+  if [ "$actual" != "$2" ]; then
+    echo "FAIL $1"
+    echo "  expected $2"
+    echo "  got      $actual"
+    failed=1
+  fi
+}
+
+# Adjacent and overlapping runs merge.
+check '{"op": "insert_runs", "runs": [[1, 3], [4, 6]]}' '{"len":6,"set":[[1,6]]}'
+check '{"op": "insert_runs", "runs": [[1, 5], [3, 8]]}' '{"len":8,"set":[[1,8]]}'
+check '{"op": "insert_runs", "runs": [[5, 9], [1, 4]]}' '{"len":9,"set":[[1,9]]}'
+check '{"op": "insert_runs", "runs": [[1, 2], [5, 6], [9, 10], [3, 8]]}' '{"len":10,"set":[[1,10]]}'
+check '{"op": "insert_runs", "runs": [[1, 2], [5, 6], [9, 10], [4, 4]]}' '{"len":7,"set":[[1,2],[4,6],[9,10]]}'
+check '{"op": "insert_runs", "runs": [[1, 10], [3, 4]]}' '{"len":10,"set":[[1,10]]}'
+check '{"op": "insert_runs", "runs": [[1, 1], [3, 3], [2, 2]]}' '{"len":3,"set":[[1,3]]}'
+check '{"op": "insert_runs", "runs": [[5, 3]]}' '{"len":0,"set":[]}'
+check '{"op": "union", "a": [1, [5, 7]], "b": [[2, 4], 9]}' '{"len":8,"set":[[1,7],9]}'
+check '{"op": "union", "a": [], "b": [[1, 3]]}' '{"len":3,"set":[[1,3]]}'
+
+# The i32::MIN and i32::MAX endpoints.
+check '{"op": "insert_runs", "runs": [[-2147483648, -2147483647], [2147483646, 2147483647]]}' \
+  '{"len":4,"set":[[-2147483648,-2147483647],[2147483646,2147483647]]}'
+check '{"op": "insert_runs", "runs": [[2147483647, 2147483647], [-2147483648, 2147483646]]}' \
+  '{"len":4294967296,"set":[[-2147483648,2147483647]]}'
+check '{"op": "insert_runs", "runs": [[0, 2147483647], [-2147483648, -1]]}' \
+  '{"len":4294967296,"set":[[-2147483648,2147483647]]}'
+check '{"op": "difference", "a": [[-2147483648, 2147483647]], "b": [-2147483648, 2147483647]}' \
+  '{"len":4294967294,"set":[[-2147483647,2147483646]]}'
+check '{"op": "difference", "a": [-2147483648, 2147483647], "b": [[-2147483647, 2147483646]]}' \
+  '{"len":2,"set":[-2147483648,2147483647]}'
+check '{"op": "within", "a": [[-2147483648, 2147483647]], "first": 2147483647, "last": 2147483647}' \
+  '{"len":1,"set":[2147483647]}'
+check '{"op": "within", "a": [[-2147483648, 2147483647]], "first": -2147483648, "last": -2147483647}' \
+  '{"len":2,"set":[[-2147483648,-2147483647]]}'
+
+# Difference against a run spanning several others, and the other way round.
+check '{"op": "difference", "a": [[1, 100]], "b": [5, [10, 20], [30, 40], 99]}' \
+  '{"len":76,"set":[[1,4],[6,9],[21,29],[41,98],100]}'
+check '{"op": "difference", "a": [[10, 20], [30, 40]], "b": [[5, 35]]}' '{"len":5,"set":[[36,40]]}'
+check '{"op": "difference", "a": [[10, 20], [30, 40]], "b": [[1, 100]]}' '{"len":0,"set":[]}'
+check '{"op": "difference", "a": [[1, 10]], "b": [[11, 20]]}' '{"len":10,"set":[[1,10]]}'
+check '{"op": "intersection", "a": [[1, 10], [20, 30]], "b": [[5, 25]]}' '{"len":12,"set":[[5,10],[20,25]]}'
+check '{"op": "within", "a": [[1, 10], [20, 30]], "first": 5, "last": 25}' '{"len":12,"set":[[5,10],[20,25]]}'
+check '{"op": "within", "a": [[1, 10], [20, 30]], "first": 11, "last": 19}' '{"len":0,"set":[]}'
+check '{"op": "within", "a": [[1, 10]], "first": 5, "last": 4}' '{"len":0,"set":[]}'
+
+# The compact JSON form: single values stand alone, runs are pairs, and
+# reading merges whatever overlaps.
+check '{"op": "parse", "set": [9, [1, 3], [2, 5], 4]}' '{"len":6,"set":[[1,5],9]}'
+check '{"op": "parse", "set": [-2147483648, [-2147483647, 2147483647]]}' \
+  '{"len":4294967296,"set":[[-2147483648,2147483647]]}'
+check '{"op": "parse", "set": [[5, 3]]}' '{"error":"empty run [5, 3]"}'
+check '{"op": "parse", "set": [[1, 2, 3]]}' '{"error":"data did not match any variant of untagged enum Run"}'
+
+# Equal sets have equal digests, however they were written.
+check '{"op": "differing", "a": [[0, 5000]], "b": [[0, 5000]]}' '{"differing":[],"equal":true}'
+check '{"op": "differing", "a": [[0, 1000], [1001, 5000]], "b": [[0, 1023], [1024, 5000]]}' '{"differing":[],"equal":true}'
+check '{"op": "differing", "a": [], "b": []}' '{"differing":[],"equal":true}'
+
+# One differing value flags exactly its bucket, of 1024 values from 0.
+check '{"op": "differing", "a": [[0, 5000]], "b": [[0, 2999], [3001, 5000]]}' '{"differing":[2],"equal":false}'
+check '{"op": "differing", "a": [[-5000, 5000]], "b": [[-5000, -2], [0, 5000]]}' '{"differing":[-1],"equal":false}'
+check '{"op": "differing", "a": [[0, 5000]], "b": [[0, 5000], 9000]}' '{"differing":[8],"equal":false}'
+check '{"op": "differing", "a": [1], "b": [2]}' '{"differing":[0],"equal":false}'
+check '{"op": "differing", "a": [[1, 3]], "b": [1, 3]}' '{"differing":[0],"equal":false}'
+check '{"op": "differing", "a": [-2147483648, 2147483647], "b": [2147483647]}' '{"differing":[-2097152],"equal":false}'
+
+# Buckets from a peer can lie outside the values, or anywhere in i64.
+check '{"op": "in_buckets", "a": [-2147483648, [-3, 3], 1030, 2147483647], "buckets": [0, 1]}' '{"len":5,"set":[[0,3],1030]}'
+check '{"op": "in_buckets", "a": [-2147483648, [-3, 3], 2147483647], "buckets": [-2097152, 2097151]}' '{"len":2,"set":[-2147483648,2147483647]}'
+check '{"op": "in_buckets", "a": [-2147483648, 2147483647], "buckets": [-2097153, 2097152, 9223372036854775807, -9223372036854775808]}' '{"len":0,"set":[]}'
+
+# Replicas that diverged during a partition converge after one sync, which
+# only transfers the values in the buckets they disagree on.
+check '{"op": "sync", "a": [[0, 10000], 20000], "b": [[0, 10000], -5, 30000]}' \
+  '{"buckets":[-1,19,29],"returned":1,"sent":2,"set":{"len":10004,"set":[-5,[0,10000],20000,30000]}}'
+check '{"op": "sync", "a": [[0, 10000]], "b": [[0, 10001]]}' \
+  '{"buckets":[9],"returned":0,"sent":786,"set":{"len":10002,"set":[[0,10001]]}}'
+check '{"op": "sync", "a": [[0, 10000]], "b": [[0, 10000]]}' \
+  '{"buckets":[],"returned":0,"sent":0,"set":{"len":10001,"set":[[0,10000]]}}'
+check '{"op": "sync", "a": [], "b": [[0, 3000]]}' \
+  '{"buckets":[0,1,2],"returned":0,"sent":3001,"set":{"len":3001,"set":[[0,3000]]}}'
+check '{"op": "sync", "a": [[0, 3000]], "b": []}' \
+  '{"buckets":[0,1,2],"returned":3001,"sent":0,"set":{"len":3001,"set":[[0,3000]]}}'
+check '{"op": "sync", "a": [-2147483648], "b": [2147483647]}' \
+  '{"buckets":[-2097152,2097151],"returned":1,"sent":1,"set":{"len":2,"set":[-2147483648,2147483647]}}'
+
+exit $failed
@@ -0,0 +1,346 @@
+//! `#[derive(Body)]` for the message bodies of a workload, re-exported by
+//! `flyio`.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{
+    parse_macro_input, Attribute, Data, DeriveInput, Error, Fields, Generics, Ident, LitStr, Meta,
+    Result, Token, Type, Variant,
+};
+
+/// Derives `Deserialize` and `flyio::Body` for the enum of messages a workload
+/// receives, and generates the enum of its replies.
+///
+/// Every variant is a message type, named after the variant in snake_case
+/// (`CommitOffsets` is `commit_offsets`) unless renamed with
+/// `#[body(rename = "...")]`. A capital run is one word: `TxnOK` is `txn_ok`.
+/// `#[body(...)]` on a field goes to serde as `#[serde(...)]`. Leave
+/// `msg_id` and `in_reply_to` out of the fields, the runtime reads those off
+/// the envelope into `Message::msg_id` and `Message::in_reply_to`.
+///
+/// With `#[body(replies = BodyOut)]` on the enum, every variant marked
+/// `#[reply]` or `#[reply(field: Type, ...)]` gets a reply in the generated
+/// `BodyOut`: `CommitOffsets` gets `BodyOut::CommitOffsetsOK`, a
+/// `commit_offsets_ok`, with `msg_id`, `in_reply_to` and those fields, and a
+/// `BodyOut::commit_offsets_ok` constructor taking those fields. `BodyOut`
+/// implements `flyio::ReplyBody`: `Node::reply` fills in the ids. Give
+/// `BodyOut` lifetimes, `replies = BodyOut<'a>`, for replies that borrow.
+///
+/// ```ignore
+/// #[derive(Body, Debug)]
+/// #[body(replies = BodyOut<'a>)]
+/// enum BodyIn {
+///     #[reply]
+///     Broadcast { message: i32 },
+///     #[reply(messages: &'a [i32])]
+///     Read,
+/// }
+/// ```
+#[proc_macro_derive(Body, attributes(body, reply))]
+pub fn derive_body(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// `#[body(replies = Name<...>)]` on the enum.
+struct Replies {
+    ident: Ident,
+    generics: Generics,
+}
+
+/// `name: Type` in `#[reply(...)]`.
+struct ReplyField {
+    ident: Ident,
+    ty: Type,
+}
+
+impl Parse for ReplyField {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let ident = input.parse()?;
+        input.parse::<Token![:]>()?;
+        let ty = input.parse()?;
+        Ok(ReplyField { ident, ty })
+    }
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2> {
+    let Data::Enum(data) = &input.data else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "Body only derives for enums",
+        ));
+    };
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "received bodies are deserialized owned, they take no generics",
+        ));
+    }
+
+    let replies = replies_of(&input.attrs)?;
+    let name = &input.ident;
+    let vis = &input.vis;
+
+    let mut kinds = Vec::new();
+    let mut mirrored = Vec::new();
+    let mut conversions = Vec::new();
+    let mut reply_variants = Vec::new();
+    let mut constructors = Vec::new();
+    let mut set_ids = Vec::new();
+    for variant in &data.variants {
+        let ident = &variant.ident;
+        let kind = kind_of(variant)?;
+        let bindings = bindings(&variant.fields);
+        let fields = mirror_fields(&variant.fields)?;
+        kinds.push(kind.clone());
+        mirrored.push(quote! {
+            #[serde(rename = #kind)]
+            #ident #fields
+        });
+        conversions.push(quote! { __Body::#ident #bindings => #name::#ident #bindings });
+
+        let Some(reply_fields) = reply_of(variant)? else {
+            continue;
+        };
+        let Some(replies) = &replies else {
+            return Err(Error::new_spanned(
+                ident,
+                "#[reply] needs #[body(replies = ...)] on the enum",
+            ));
+        };
+
+        let reply = format_ident!("{}OK", ident);
+        let reply_kind = format!("{kind}_ok");
+        let constructor = Ident::new(
+            &reply_kind.replace(|c: char| !c.is_alphanumeric(), "_"),
+            Span::call_site(),
+        );
+        let names: Vec<_> = reply_fields.iter().map(|field| &field.ident).collect();
+        let types: Vec<_> = reply_fields.iter().map(|field| &field.ty).collect();
+        if let Some(name) = names
+            .iter()
+            .find(|name| **name == "msg_id" || **name == "in_reply_to")
+        {
+            return Err(Error::new_spanned(
+                name,
+                "every reply already has this field",
+            ));
+        }
+        let out = &replies.ident;
+        let doc = format!("Replies to a `{kind}`, `Node::reply` fills in the ids.");
+
+        reply_variants.push(quote! {
+            #[serde(rename = #reply_kind)]
+            #reply {
+                msg_id: usize,
+                in_reply_to: usize,
+                #(#names: #types),*
+            }
+        });
+        constructors.push(quote! {
+            #[doc = #doc]
+            #[allow(dead_code)]
+            #vis fn #constructor(#(#names: #types),*) -> Self {
+                #out::#reply {
+                    msg_id: 0,
+                    in_reply_to: 0,
+                    #(#names),*
+                }
+            }
+        });
+        set_ids.push(quote! {
+            #out::#reply {
+                ref mut msg_id,
+                ref mut in_reply_to,
+                ..
+            } => (msg_id, in_reply_to)
+        });
+    }
+
+    let deserialize = quote! {
+        const _: () = {
+            #[derive(::serde::Deserialize)]
+            #[serde(tag = "type")]
+            enum __Body {
+                #(#mirrored,)*
+            }
+
+            impl<'de> ::serde::Deserialize<'de> for #name {
+                fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+                where
+                    D: ::serde::Deserializer<'de>,
+                {
+                    let body = <__Body as ::serde::Deserialize>::deserialize(deserializer)?;
+                    Ok(match body {
+                        #(#conversions,)*
+                    })
+                }
+            }
+
+            impl ::flyio::Body for #name {
+                const KINDS: &'static [&'static str] = &[#(#kinds),*];
+            }
+        };
+    };
+
+    let Some(Replies {
+        ident: out,
+        generics,
+    }) = replies
+    else {
+        return Ok(deserialize);
+    };
+    let (impl_generics, type_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        #deserialize
+
+        #[derive(Debug, ::serde::Serialize)]
+        #[serde(tag = "type")]
+        #vis enum #out #impl_generics #where_clause {
+            #(#reply_variants,)*
+        }
+
+        impl #impl_generics #out #type_generics #where_clause {
+            #(#constructors)*
+        }
+
+        impl #impl_generics ::flyio::ReplyBody for #out #type_generics #where_clause {
+            fn set_ids(&mut self, msg_id: usize, in_reply_to: usize) {
+                let ids: (&mut usize, &mut usize) = match *self {
+                    #(#set_ids,)*
+                };
+                *ids.0 = msg_id;
+                *ids.1 = in_reply_to;
+            }
+        }
+    })
+}
+
+fn replies_of(attrs: &[Attribute]) -> Result<Option<Replies>> {
+    let mut replies = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("body")) {
+        attr.parse_nested_meta(|meta| {
+            if !meta.path.is_ident("replies") {
+                return Err(meta.error("expected `replies = Name`"));
+            }
+            let value = meta.value()?;
+            let ident = value.parse()?;
+            let generics = value.parse()?;
+            replies = Some(Replies { ident, generics });
+            Ok(())
+        })?;
+    }
+    Ok(replies)
+}
+
+/// The message type of `variant`, snake_case of its name unless renamed.
+fn kind_of(variant: &Variant) -> Result<String> {
+    let mut kind = None;
+    for attr in variant
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("body"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if !meta.path.is_ident("rename") {
+                return Err(meta.error("expected `rename = \"...\"`"));
+            }
+            kind = Some(meta.value()?.parse::<LitStr>()?.value());
+            Ok(())
+        })?;
+    }
+    Ok(kind.unwrap_or_else(|| snake_case(&variant.ident.to_string())))
+}
+
+/// The fields of the reply to `variant`, if it has one.
+fn reply_of(variant: &Variant) -> Result<Option<Vec<ReplyField>>> {
+    let Some(attr) = variant
+        .attrs
+        .iter()
+        .find(|attr| attr.path().is_ident("reply"))
+    else {
+        return Ok(None);
+    };
+
+    match &attr.meta {
+        Meta::Path(_) => Ok(Some(Vec::new())),
+        Meta::List(list) => {
+            let fields =
+                list.parse_args_with(Punctuated::<ReplyField, Token![,]>::parse_terminated)?;
+            Ok(Some(fields.into_iter().collect()))
+        }
+        Meta::NameValue(_) => Err(Error::new_spanned(
+            attr,
+            "expected #[reply] or #[reply(field: Type, ...)]",
+        )),
+    }
+}
+
+/// The fields of a variant as they go in the mirrored enum, with their
+/// `#[body(...)]` turned into `#[serde(...)]`.
+fn mirror_fields(fields: &Fields) -> Result<TokenStream2> {
+    let mirrored = fields
+        .iter()
+        .map(|field| {
+            let attrs = field
+                .attrs
+                .iter()
+                .map(|attr| {
+                    if !attr.path().is_ident("body") {
+                        return Ok(quote! { #attr });
+                    }
+                    let args = &attr.meta.require_list()?.tokens;
+                    Ok(quote! { #[serde(#args)] })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let ident = field.ident.iter();
+            let ty = &field.ty;
+            Ok(quote! { #(#attrs)* #(#ident:)* #ty })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(match fields {
+        Fields::Named(_) => quote! { { #(#mirrored),* } },
+        Fields::Unnamed(_) => quote! { ( #(#mirrored),* ) },
+        Fields::Unit => TokenStream2::new(),
+    })
+}
+
+/// A pattern binding every field of a variant, which also builds it back.
+fn bindings(fields: &Fields) -> TokenStream2 {
+    match fields {
+        Fields::Named(named) => {
+            let idents = named.named.iter().map(|field| &field.ident);
+            quote! { { #(#idents),* } }
+        }
+        Fields::Unnamed(unnamed) => {
+            let idents = (0..unnamed.unnamed.len()).map(|index| format_ident!("field{}", index));
+            quote! { ( #(#idents),* ) }
+        }
+        Fields::Unit => TokenStream2::new(),
+    }
+}
+
+/// `CommitOffsets` is `commit_offsets`, a run of capitals is one word but
+/// for its last one when that starts the next: `TxnOK` is `txn_ok`,
+/// `HTTPServer` is `http_server`.
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::new();
+    for (index, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() && index > 0 {
+            let previous = chars[index - 1];
+            let next_lower = chars.get(index + 1).is_some_and(|next| next.is_lowercase());
+            if !previous.is_uppercase() || next_lower {
+                snake.push('_');
+            }
+        }
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}
@@ -0,0 +1,7 @@
+#!/usr/bin/env bash
+
+set -e
+
+cargo build --bin g_counter
+
+maelstrom test -w g-counter --bin "../target/debug/g_counter" --node-count 3 --rate 100 --time-limit 20 --nemesis partition
@@ -0,0 +1,67 @@
+use anyhow::Result;
+use flyio::{Body, Handler, Kv, Message, Node};
+
+/// The one key in `seq-kv` all the nodes add to.
+const KEY: &str = "counter";
+
+/// Requests handled at once, each one mostly waits on `seq-kv`.
+const WORKERS: usize = 8;
+
+#[derive(Body, Debug)]
+#[body(replies = BodyOut)]
+enum BodyIn {
+    #[reply]
+    Add { delta: u64 },
+    #[reply(value: u64)]
+    Read,
+}
+
+#[derive(Clone)]
+struct Counter {
+    kv: Kv,
+}
+
+impl Counter {
+    fn add(&self, node: &mut Node, delta: u64) -> Result<()> {
+        loop {
+            let current = self.kv.read::<u64>(node, KEY)?.unwrap_or(0);
+            if self.kv.cas(node, KEY, current, current + delta, true)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// `seq-kv` may serve a stale read, a no-op cas only goes through when
+    /// the value is still the latest one.
+    fn read(&self, node: &mut Node) -> Result<u64> {
+        loop {
+            let current = self.kv.read::<u64>(node, KEY)?.unwrap_or(0);
+            if self.kv.cas(node, KEY, current, current, true)? {
+                return Ok(current);
+            }
+        }
+    }
+}
+
+impl Handler for Counter {
+    type Body = BodyIn;
+
+    fn handle(&mut self, node: &mut Node, message: Message<BodyIn>) -> Result<()> {
+        match message.body {
+            BodyIn::Add { delta } => {
+                // a timed out cas comes back to the client as an
+                // indefinite `timeout` error
+                self.add(node, delta)?;
+                node.reply(&message, BodyOut::add_ok())
+            }
+            BodyIn::Read => {
+                let value = self.read(node)?;
+                node.reply(&message, BodyOut::read_ok(value))
+            }
+        }
+    }
+}
+
+pub fn main() -> Result<()> {
+    Node::run_concurrent(WORKERS, |_| Counter { kv: Kv::seq() })
+}
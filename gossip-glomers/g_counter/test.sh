@@ -0,0 +1,21 @@
+#!/usr/bin/env bash
+
+set -e
+
+cargo build --bin g_counter
+
+# plays seq-kv by hand: the first read finds no key, the cas goes through
+{
+  echo '{"src":"c1", "dest": "n1", "body":{"type":"init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1","n2","n3"]}}'
+  echo '{"src":"c1", "dest": "n1", "body":{"type": "add", "msg_id": 2, "delta": 5}}'
+  sleep 0.1
+  echo '{"src":"seq-kv", "dest": "n1", "body":{"type": "error", "in_reply_to": 1, "code": 20, "text": "key does not exist"}}'
+  sleep 0.1
+  echo '{"src":"seq-kv", "dest": "n1", "body":{"type": "cas_ok", "in_reply_to": 2}}'
+  echo '{"src":"c1", "dest": "n1", "body":{"type": "read", "msg_id": 3}}'
+  sleep 0.1
+  echo '{"src":"seq-kv", "dest": "n1", "body":{"type": "read_ok", "in_reply_to": 4, "value": 5}}'
+  sleep 0.1
+  echo '{"src":"seq-kv", "dest": "n1", "body":{"type": "cas_ok", "in_reply_to": 5}}'
+  sleep 0.1
+} | ../target/debug/g_counter
@@ -0,0 +1,22 @@
+use anyhow::{bail, Context, Result};
+use harness::checker;
+use harness::history::History;
+use std::env;
+use std::path::PathBuf;
+
+/// Checks a history saved with `--history`, printing the verdict as JSON.
+fn main() -> Result<()> {
+    let path: PathBuf = env::args()
+        .nth(1)
+        .context("usage: check HISTORY.jsonl")?
+        .into();
+
+    let history = History::load(&path)?;
+    let check = checker::check(&history);
+    println!("{}", serde_json::to_string_pretty(&check)?);
+
+    if !check.valid {
+        bail!("{} is not valid", path.display());
+    }
+    Ok(())
+}
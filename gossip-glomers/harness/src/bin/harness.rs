@@ -0,0 +1,24 @@
+use anyhow::{bail, Result};
+use harness::{cli, workload};
+use std::env;
+
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!(
+            "usage: harness --bin PATH --workload NAME [options]\n\n{}",
+            cli::USAGE
+        );
+        return Ok(());
+    }
+
+    let args = cli::parse(args)?;
+    if args.config.bin.as_os_str().is_empty() {
+        bail!("--bin is required");
+    }
+    let mut workload = workload::by_name(&args.workload)?;
+
+    eprintln!("[harness] seed {}", args.config.seed);
+    let report = harness::run(args.config.clone(), workload.as_mut())?;
+    cli::conclude(&report, &args)
+}
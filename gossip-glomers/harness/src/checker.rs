@@ -0,0 +1,492 @@
+//! The properties Maelstrom checks, verified over a recorded [`History`].
+//!
+//! Every workload present in the history is checked: `echo`, `generate`,
+//! `broadcast`/`read`, `add`/`read` (g-counter) and `send`/`poll` with their
+//! offset commits (kafka).
+
+use crate::history::{EventKind, History, Operation};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// How many violations are listed in full, the others are only counted.
+const LISTED: usize = 20;
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Counts {
+    pub ok: u64,
+    pub fail: u64,
+    pub info: u64,
+}
+
+/// Operations breaking a property, pointing at their events in the history.
+#[derive(Serialize, Debug, Clone)]
+pub struct Violation {
+    pub property: &'static str,
+    /// Indices of the events involved, the first one is where it went wrong.
+    pub events: Vec<usize>,
+    pub description: String,
+}
+
+/// The verdict on a history.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Check {
+    pub valid: bool,
+    /// How each kind of operation ended.
+    pub operations: BTreeMap<String, Counts>,
+    pub violation_counts: BTreeMap<&'static str, usize>,
+    /// The first violations in history order.
+    pub violations: Vec<Violation>,
+}
+
+pub fn check(history: &History) -> Check {
+    let ops = history.operations();
+
+    let mut violations = Vec::new();
+    echo(&ops, &mut violations);
+    generate(&ops, &mut violations);
+    broadcast(&ops, &mut violations);
+    counter(&ops, &mut violations);
+    kafka(&ops, &mut violations);
+
+    let mut operations: BTreeMap<String, Counts> = BTreeMap::new();
+    for op in &ops {
+        let counts = operations.entry(op.f().into()).or_default();
+        match op.kind() {
+            EventKind::Ok => counts.ok += 1,
+            EventKind::Fail => counts.fail += 1,
+            EventKind::Invoke | EventKind::Info => counts.info += 1,
+        }
+    }
+
+    let mut violation_counts = BTreeMap::new();
+    for violation in &violations {
+        *violation_counts.entry(violation.property).or_default() += 1;
+    }
+
+    violations.sort_by_key(|violation| violation.events.iter().min().copied());
+    violations.truncate(LISTED);
+
+    Check {
+        valid: violations.is_empty(),
+        operations,
+        violation_counts,
+        violations,
+    }
+}
+
+fn violation(property: &'static str, events: Vec<usize>, description: String) -> Violation {
+    Violation {
+        property,
+        events,
+        description,
+    }
+}
+
+/// Completions of `f` that ended with an `_ok`, with their reply.
+fn ok<'a>(
+    ops: &'a [Operation<'a>],
+    f: &'a str,
+) -> impl Iterator<Item = (&'a Operation<'a>, &'a Value)> {
+    ops.iter()
+        .filter(move |op| op.f() == f)
+        .filter_map(|op| op.reply().map(|reply| (op, reply)))
+}
+
+fn echo(ops: &[Operation], violations: &mut Vec<Violation>) {
+    for (op, reply) in ok(ops, "echo") {
+        if reply["echo"] != op.request()["echo"] {
+            violations.push(violation(
+                "echo-mismatch",
+                op.indices(),
+                format!(
+                    "{} echoed {} for {}",
+                    op.invoke.node,
+                    reply["echo"],
+                    op.request()["echo"]
+                ),
+            ));
+        }
+    }
+}
+
+fn generate(ops: &[Operation], violations: &mut Vec<Violation>) {
+    let mut ids: HashMap<String, &Operation> = HashMap::new();
+
+    for (op, reply) in ok(ops, "generate") {
+        let id = reply["id"].to_string();
+        match ids.get(&id) {
+            Some(first) => {
+                let mut events = first.indices();
+                events.extend(op.indices());
+                violations.push(violation(
+                    "duplicate-id",
+                    events,
+                    format!(
+                        "{} and {} both generated {id}",
+                        first.invoke.node, op.invoke.node
+                    ),
+                ));
+            }
+            None => {
+                ids.insert(id, op);
+            }
+        }
+    }
+}
+
+/// Reads after the run if the history marks them, otherwise the last read
+/// of every node.
+fn final_reads<'a>(reads: &[&'a Operation<'a>]) -> Vec<&'a Operation<'a>> {
+    if reads.iter().any(|op| op.invoke.is_final) {
+        return reads
+            .iter()
+            .copied()
+            .filter(|op| op.invoke.is_final)
+            .collect();
+    }
+
+    let mut last: BTreeMap<&str, &Operation> = BTreeMap::new();
+    for op in reads.iter().filter(|op| op.is_ok()) {
+        last.insert(&op.invoke.node, op);
+    }
+    last.into_values().collect()
+}
+
+fn broadcast(ops: &[Operation], violations: &mut Vec<Violation>) {
+    let mut sent = HashSet::new();
+    let mut acked: BTreeMap<i64, &Operation> = BTreeMap::new();
+    for op in ops.iter().filter(|op| op.f() == "broadcast") {
+        if let Some(value) = op.request()["message"].as_i64() {
+            sent.insert(value);
+            if op.is_ok() {
+                acked.insert(value, op);
+            }
+        }
+    }
+    if sent.is_empty() {
+        return;
+    }
+
+    let reads: Vec<&Operation> = ops
+        .iter()
+        .filter(|op| op.f() == "read")
+        .filter(|op| op.reply().is_none_or(|reply| reply["messages"].is_array()))
+        .collect();
+
+    for op in &reads {
+        let Some(reply) = op.reply() else { continue };
+        let phantom: Vec<i64> = values(reply)
+            .into_iter()
+            .filter(|value| !sent.contains(value))
+            .collect();
+        if !phantom.is_empty() {
+            violations.push(violation(
+                "phantom-read",
+                op.indices(),
+                format!(
+                    "{} read values never broadcast: {phantom:?}",
+                    op.invoke.node
+                ),
+            ));
+        }
+    }
+
+    let mut missing: BTreeMap<i64, Vec<&Operation>> = BTreeMap::new();
+    for read in final_reads(&reads) {
+        let Some(reply) = read.reply() else {
+            violations.push(violation(
+                "missing-final-read",
+                read.indices(),
+                format!("{} never answered its final read", read.invoke.node),
+            ));
+            continue;
+        };
+
+        let seen: HashSet<i64> = values(reply).into_iter().collect();
+        for value in acked.keys().filter(|value| !seen.contains(value)) {
+            missing.entry(*value).or_default().push(read);
+        }
+    }
+
+    for (value, reads) in missing {
+        let mut events = acked[&value].indices();
+        events.extend(reads[0].indices());
+        let nodes: Vec<&str> = reads.iter().map(|read| read.invoke.node.as_str()).collect();
+        violations.push(violation(
+            "lost-broadcast",
+            events,
+            format!("acknowledged value {value} is missing from the final reads of {nodes:?}"),
+        ));
+    }
+}
+
+fn values(reply: &Value) -> Vec<i64> {
+    reply["messages"]
+        .as_array()
+        .map(|values| values.iter().filter_map(Value::as_i64).collect())
+        .unwrap_or_default()
+}
+
+/// A grow-only counter: no read exceeds the adds that might be done when it
+/// ended, and the final reads agree on a value between the acknowledged adds
+/// and the ones that might have happened. Reads during the run may be stale.
+fn counter(ops: &[Operation], violations: &mut Vec<Violation>) {
+    let adds: Vec<(&Operation, u64)> = ops
+        .iter()
+        .filter(|op| op.f() == "add" && op.kind() != EventKind::Fail)
+        .filter_map(|op| op.request()["delta"].as_u64().map(|delta| (op, delta)))
+        .collect();
+    let reads: Vec<(&Operation, u64)> = ok(ops, "read")
+        .filter_map(|(op, reply)| reply["value"].as_u64().map(|value| (op, value)))
+        .collect();
+    if adds.is_empty() && reads.is_empty() {
+        return;
+    }
+
+    for &(read, value) in &reads {
+        let lower: u64 = adds
+            .iter()
+            .filter(|(add, _)| add.is_ok() && add.completed_at() < read.invoke.time)
+            .map(|(_, delta)| delta)
+            .sum();
+        let upper: u64 = adds
+            .iter()
+            .filter(|(add, _)| add.invoke.time < read.completed_at())
+            .map(|(_, delta)| delta)
+            .sum();
+
+        if value > upper || (read.invoke.is_final && value < lower) {
+            violations.push(violation(
+                "counter-out-of-bounds",
+                read.indices(),
+                format!(
+                    "{} read {value}, expected between {lower} and {upper}",
+                    read.invoke.node
+                ),
+            ));
+        }
+    }
+
+    let finals: Vec<&(&Operation, u64)> = reads
+        .iter()
+        .filter(|(read, _)| read.invoke.is_final)
+        .collect();
+    if let Some(&&(first, expected)) = finals.first() {
+        for &&(read, value) in &finals[1..] {
+            if value != expected {
+                let mut events = first.indices();
+                events.extend(read.indices());
+                violations.push(violation(
+                    "counter-divergence",
+                    events,
+                    format!(
+                        "final reads disagree: {} read {expected}, {} read {value}",
+                        first.invoke.node, read.invoke.node
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+/// `[[offset, msg], ...]` per key, as in `poll_ok`.
+fn polled(reply: &Value) -> BTreeMap<String, Vec<(u64, Value)>> {
+    let Some(msgs) = reply["msgs"].as_object() else {
+        return BTreeMap::new();
+    };
+
+    msgs.iter()
+        .map(|(key, pairs)| {
+            let pairs = pairs
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|pair| Some((pair.get(0)?.as_u64()?, pair.get(1)?.clone())))
+                .collect();
+            (key.clone(), pairs)
+        })
+        .collect()
+}
+
+/// Kafka-style logs: offsets are unique and grow with real time, polls see
+/// what was sent at each offset, in order, without skipping acknowledged
+/// sends, and committed offsets never go back.
+fn kafka(ops: &[Operation], violations: &mut Vec<Violation>) {
+    // (key, offset) -> what was sent there and by which operation
+    let mut logs: BTreeMap<String, BTreeMap<u64, (&Value, &Operation)>> = BTreeMap::new();
+    let mut sends: BTreeMap<String, Vec<(&Operation, u64)>> = BTreeMap::new();
+
+    for (op, reply) in ok(ops, "send") {
+        let (Some(key), Some(offset)) = (op.request()["key"].as_str(), reply["offset"].as_u64())
+        else {
+            continue;
+        };
+        let log = logs.entry(key.into()).or_default();
+        if let Some((_, first)) = log.get(&offset) {
+            let mut events = first.indices();
+            events.extend(op.indices());
+            violations.push(violation(
+                "kafka-duplicate-offset",
+                events,
+                format!("two sends to {key} were both given offset {offset}"),
+            ));
+            continue;
+        }
+        log.insert(offset, (&op.request()["msg"], op));
+        sends.entry(key.into()).or_default().push((op, offset));
+    }
+
+    for (key, sends) in &mut sends {
+        nonmonotonic_offsets(key, sends, violations);
+    }
+
+    for (op, reply) in ok(ops, "poll") {
+        for (key, pairs) in polled(reply) {
+            poll(op, &key, &pairs, logs.get(&key), violations);
+        }
+    }
+
+    committed_offsets(ops, violations);
+}
+
+/// A send that started after another one to the same key was acknowledged
+/// must get a higher offset.
+fn nonmonotonic_offsets(
+    key: &str,
+    sends: &mut [(&Operation, u64)],
+    violations: &mut Vec<Violation>,
+) {
+    let mut by_completion: Vec<&(&Operation, u64)> = sends.iter().collect();
+    by_completion.sort_by_key(|(op, _)| op.completed_at());
+
+    let mut by_invocation: Vec<&(&Operation, u64)> = sends.iter().collect();
+    by_invocation.sort_by_key(|(op, _)| op.invoke.time);
+
+    let mut done = by_completion.into_iter().peekable();
+    let mut highest: Option<&(&Operation, u64)> = None;
+    for send in by_invocation {
+        while let Some(earlier) = done.next_if(|(op, _)| op.completed_at() < send.0.invoke.time) {
+            if highest.is_none_or(|(_, offset)| earlier.1 > *offset) {
+                highest = Some(earlier);
+            }
+        }
+
+        if let Some((earlier, offset)) = highest {
+            if send.1 <= *offset {
+                let mut events = earlier.indices();
+                events.extend(send.0.indices());
+                violations.push(violation(
+                    "kafka-nonmonotonic-offset",
+                    events,
+                    format!(
+                        "a send to {key} got offset {} after an earlier one got {offset}",
+                        send.1
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+fn poll(
+    op: &Operation,
+    key: &str,
+    pairs: &[(u64, Value)],
+    log: Option<&BTreeMap<u64, (&Value, &Operation)>>,
+    violations: &mut Vec<Violation>,
+) {
+    for window in pairs.windows(2) {
+        if window[0].0 >= window[1].0 {
+            violations.push(violation(
+                "kafka-nonmonotonic-poll",
+                op.indices(),
+                format!(
+                    "{} polled {key} offsets {} then {}",
+                    op.invoke.node, window[0].0, window[1].0
+                ),
+            ));
+        }
+    }
+
+    let Some(log) = log else { return };
+
+    for (offset, msg) in pairs {
+        if let Some((sent, send)) = log.get(offset) {
+            if *sent != msg {
+                let mut events = send.indices();
+                events.extend(op.indices());
+                violations.push(violation(
+                    "kafka-inconsistent-offset",
+                    events,
+                    format!("{key} offset {offset} was sent {sent} but polled as {msg}"),
+                ));
+            }
+        }
+    }
+
+    // out of order polls are reported above, the range they cover still counts
+    let seen: HashSet<u64> = pairs.iter().map(|(offset, _)| *offset).collect();
+    let (Some(first), Some(last)) = (seen.iter().min(), seen.iter().max()) else {
+        return;
+    };
+    for (offset, (_, send)) in log.range(first..=last) {
+        if !seen.contains(offset) && send.completed_at() < op.invoke.time {
+            let mut events = send.indices();
+            events.extend(op.indices());
+            violations.push(violation(
+                "kafka-lost-write",
+                events,
+                format!(
+                    "{} polled {key} from {first} to {last} but skipped acknowledged offset {offset}",
+                    op.invoke.node
+                ),
+            ));
+        }
+    }
+}
+
+/// `list_committed_offsets` never reports less than a commit acknowledged
+/// before it started.
+fn committed_offsets(ops: &[Operation], violations: &mut Vec<Violation>) {
+    let commits: Vec<(&Operation, &serde_json::Map<String, Value>)> = ok(ops, "commit_offsets")
+        .filter_map(|(op, _)| {
+            op.request()["offsets"]
+                .as_object()
+                .map(|offsets| (op, offsets))
+        })
+        .collect();
+
+    for (op, reply) in ok(ops, "list_committed_offsets") {
+        let Some(listed) = reply["offsets"].as_object() else {
+            continue;
+        };
+        let asked = |key: &String| {
+            op.request()["keys"]
+                .as_array()
+                .is_some_and(|keys| keys.iter().any(|asked| asked == key))
+        };
+
+        for (commit, offsets) in &commits {
+            if commit.completed_at() >= op.invoke.time {
+                continue;
+            }
+            for (key, committed) in offsets.iter().filter(|(key, _)| asked(key)) {
+                let committed = committed.as_u64().unwrap_or_default();
+                let current = listed.get(key).and_then(Value::as_u64);
+                if current.is_none_or(|current| current < committed) {
+                    let mut events = commit.indices();
+                    events.extend(op.indices());
+                    violations.push(violation(
+                        "kafka-committed-regression",
+                        events,
+                        format!(
+                            "{key} was committed at {committed} but listed as {} afterwards",
+                            current.map_or("nothing".into(), |current| current.to_string())
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! Command line front-ends, shared by the `harness` binary and the node
+//! binaries that can simulate themselves.
+
+use crate::{workload, Config, Report};
+use anyhow::{bail, Context, Result};
+use flyio::{Handler, Node, Rng};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const USAGE: &str = "\
+options:
+  --workload NAME         echo, unique-ids or broadcast
+  --node-count N          nodes to run (1)
+  --time-limit SECS       how long to issue operations (10)
+  --rate N                operations per second (10)
+  --latency MS            delay of every message (0)
+  --jitter MS             random extra delay, reorders messages (0)
+  --drop P                probability of losing a message between nodes (0)
+  --nemesis partition     partition the nodes in two halves now and then
+  --nemesis crash         kill a random node now and then and start it again,
+                          with STATE_DIR set (not when simulating)
+  --nemesis-interval SECS time between two nemesis actions (5)
+  --settle SECS           time to converge before the final reads (5)
+  --seed N                replay a previous run
+  --history PATH          save the client history as JSON lines";
+
+/// What the command line asks for.
+pub struct Args {
+    pub config: Config,
+    pub workload: String,
+    /// Where to save the history.
+    pub history: Option<PathBuf>,
+}
+
+fn value<T: FromStr>(flag: &str, value: Option<String>) -> Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let value = value.with_context(|| format!("{flag} needs a value"))?;
+    value
+        .parse()
+        .with_context(|| format!("invalid value for {flag}: {value:?}"))
+}
+
+fn secs(flag: &str, arg: Option<String>) -> Result<Duration> {
+    Ok(Duration::from_secs_f64(value(flag, arg)?))
+}
+
+fn millis(flag: &str, arg: Option<String>) -> Result<Duration> {
+    Ok(Duration::from_millis(value(flag, arg)?))
+}
+
+/// Parses the options above plus `--bin PATH`. Without `--seed` the seed
+/// comes from the time.
+pub fn parse<I>(args: I) -> Result<Args>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut config = Config {
+        seed: Rng::seed_from_time(),
+        ..Config::default()
+    };
+    let mut workload = None;
+    let mut history = None;
+
+    let mut args = args.into_iter();
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--bin" => config.bin = value::<String>(&flag, args.next())?.into(),
+            "--workload" | "-w" => workload = Some(value::<String>(&flag, args.next())?),
+            "--node-count" => config.node_count = value(&flag, args.next())?,
+            "--time-limit" => config.time_limit = secs(&flag, args.next())?,
+            "--rate" => config.rate = value(&flag, args.next())?,
+            "--latency" => config.latency = millis(&flag, args.next())?,
+            "--jitter" => config.jitter = millis(&flag, args.next())?,
+            "--drop" => config.drop = value(&flag, args.next())?,
+            "--nemesis" => match value::<String>(&flag, args.next())?.as_str() {
+                "partition" => config.partition = true,
+                "crash" => config.crash = true,
+                other => bail!("unknown nemesis {other:?}, expected partition or crash"),
+            },
+            "--nemesis-interval" => config.nemesis_interval = secs(&flag, args.next())?,
+            "--settle" => config.settle = secs(&flag, args.next())?,
+            "--seed" => config.seed = value(&flag, args.next())?,
+            "--history" => history = Some(value::<String>(&flag, args.next())?.into()),
+            _ => bail!("unknown argument {flag:?}\n\n{USAGE}"),
+        }
+    }
+
+    if config.node_count == 0 {
+        bail!("--node-count must be at least 1");
+    }
+    Ok(Args {
+        config,
+        workload: workload.context("--workload is required")?,
+        history,
+    })
+}
+
+/// Prints the report and saves the history if asked, failing when the run
+/// is not valid.
+pub fn conclude(report: &Report, args: &Args) -> Result<()> {
+    println!("{report}");
+    if let Some(path) = &args.history {
+        report.history.save(path)?;
+    }
+    if !report.is_valid() {
+        bail!("invalid run, replay it with --seed {}", report.seed);
+    }
+    Ok(())
+}
+
+/// Runs a workload against a simulation of `make_handler` nodes as
+/// configured by `args`, e.g. for a `simulate` subcommand.
+pub fn simulate<I, H, F>(args: I, make_handler: F) -> Result<()>
+where
+    I: IntoIterator<Item = String>,
+    H: Handler,
+    F: FnMut(&Node) -> H,
+{
+    let args = parse(args)?;
+    let mut workload = workload::by_name(&args.workload)?;
+
+    eprintln!("[harness] seed {}", args.config.seed);
+    let report = crate::simulate(args.config.clone(), workload.as_mut(), make_handler)?;
+    conclude(&report, &args)
+}
@@ -0,0 +1,32 @@
+use anyhow::Result;
+use flyio::sim::Traffic;
+use std::collections::HashSet;
+use std::time::Instant;
+
+/// The nodes under test and the network between them, as seen by the client.
+pub(crate) trait Cluster {
+    fn now(&self) -> Instant;
+
+    /// Delivers a message from outside the cluster (a client or a service) to
+    /// node `dest`, such messages are never lost.
+    fn send(&mut self, dest: &str, line: String) -> Result<()>;
+
+    /// Lets the nodes run until `until`, or less, and returns what they sent
+    /// outside the cluster meanwhile.
+    fn step(&mut self, until: Instant) -> Result<Vec<String>>;
+
+    /// Cuts `side` off from the other nodes until [`Cluster::heal`].
+    fn partition(&mut self, side: HashSet<String>);
+
+    fn heal(&mut self);
+
+    fn is_partitioned(&self) -> bool;
+
+    /// Kills node `node` and starts it again, handing it `init` before any
+    /// other message.
+    fn restart(&mut self, node: &str, init: String) -> Result<()>;
+
+    fn traffic(&self) -> Traffic;
+
+    fn shutdown(&mut self) -> Result<()>;
+}
@@ -0,0 +1,174 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+/// What happened to an operation, in Maelstrom's terms.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    /// The client sent the request.
+    Invoke,
+    /// The node replied with the `_ok` body.
+    Ok,
+    /// The node replied with an `error`, the operation did not happen.
+    Fail,
+    /// No reply in time, the operation may or may not have happened.
+    Info,
+}
+
+/// One line of a history.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Event {
+    /// Position in the history.
+    pub index: usize,
+    #[serde(rename = "type")]
+    pub kind: EventKind,
+    /// The operation, shared by its invocation and its completion.
+    pub op: u64,
+    pub node: String,
+    /// Nanoseconds since the start of the run.
+    pub time: u64,
+    /// Issued after the run, once the network healed and the nodes settled.
+    #[serde(default, rename = "final", skip_serializing_if = "is_false")]
+    pub is_final: bool,
+    /// The request for an invocation, the reply for a completion, nothing
+    /// when timed out.
+    #[serde(default)]
+    pub body: Value,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// The client's view of a run: every invocation and completion in order.
+///
+/// Stored as JSON lines, one [`Event`] per line, e.g.
+///
+/// ```text
+/// {"index":0,"type":"invoke","op":7,"node":"n1","time":1200,"body":{"type":"add","delta":3,"msg_id":7}}
+/// {"index":1,"type":"ok","op":7,"node":"n1","time":5600,"body":{"type":"add_ok","in_reply_to":7}}
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct History {
+    pub events: Vec<Event>,
+}
+
+/// An invocation and, unless still pending, how it ended.
+pub struct Operation<'a> {
+    pub invoke: &'a Event,
+    pub completion: Option<&'a Event>,
+}
+
+impl Operation<'_> {
+    /// The request type, e.g. `broadcast`.
+    pub fn f(&self) -> &str {
+        self.invoke.body["type"].as_str().unwrap_or_default()
+    }
+
+    pub fn request(&self) -> &Value {
+        &self.invoke.body
+    }
+
+    pub fn kind(&self) -> EventKind {
+        self.completion.map_or(EventKind::Info, |event| event.kind)
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.kind() == EventKind::Ok
+    }
+
+    /// The `_ok` reply, if that is how it ended.
+    pub fn reply(&self) -> Option<&Value> {
+        self.completion
+            .filter(|event| event.kind == EventKind::Ok)
+            .map(|event| &event.body)
+    }
+
+    /// When it ended, never for an operation without a completion.
+    pub fn completed_at(&self) -> u64 {
+        self.completion.map_or(u64::MAX, |event| event.time)
+    }
+
+    /// The events to point at when it is part of a violation.
+    pub fn indices(&self) -> Vec<usize> {
+        let mut indices = vec![self.invoke.index];
+        indices.extend(self.completion.map(|event| event.index));
+        indices
+    }
+}
+
+impl History {
+    pub fn record(
+        &mut self,
+        kind: EventKind,
+        op: u64,
+        node: &str,
+        time: Duration,
+        is_final: bool,
+        body: Value,
+    ) {
+        self.events.push(Event {
+            index: self.events.len(),
+            kind,
+            op,
+            node: node.into(),
+            time: time.as_nanos() as u64,
+            is_final,
+            body,
+        });
+    }
+
+    /// Every operation in invocation order, paired with its completion.
+    pub fn operations(&self) -> Vec<Operation<'_>> {
+        let mut operations = Vec::new();
+        let mut open = HashMap::new();
+
+        for event in &self.events {
+            if event.kind == EventKind::Invoke {
+                open.insert(event.op, operations.len());
+                operations.push(Operation {
+                    invoke: event,
+                    completion: None,
+                });
+            } else if let Some(i) = open.remove(&event.op) {
+                operations[i].completion = Some(event);
+            }
+        }
+
+        operations
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+
+        let mut events = Vec::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let event = serde_json::from_str(&line)
+                .with_context(|| format!("{}:{}: invalid event", path.display(), number + 1))?;
+            events.push(event);
+        }
+
+        Ok(History { events })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = File::create(path).with_context(|| format!("creating {}", path.display()))?;
+
+        let mut out = BufWriter::new(file);
+        for event in &self.events {
+            serde_json::to_writer(&mut out, event)?;
+            out.write_all(b"\n")?;
+        }
+        Ok(out.flush()?)
+    }
+}
@@ -0,0 +1,563 @@
+//! A stand-in for Maelstrom that needs nothing but the node binaries.
+//!
+//! The nodes run as child processes, every line they print is routed to the
+//! stdin of its destination after `latency`, possibly dropped or cut off by
+//! a partition, while a single client drives a workload against them. The
+//! client records a [`History`] of its operations, which the [`checker`]
+//! verifies.
+//!
+//! The same workloads also run against a [`flyio::sim::Simulation`] of the
+//! handlers, see [`simulate`], where the seed replays a run exactly.
+
+pub mod checker;
+pub mod cli;
+mod cluster;
+pub mod history;
+mod network;
+mod process;
+mod simulated;
+pub mod workload;
+
+use anyhow::{bail, Context, Result};
+use checker::Check;
+use cluster::Cluster;
+use flyio::sim::Simulation;
+use flyio::{ErrorCode, Handler, Node, Rng};
+use history::{EventKind, History};
+use network::Network;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use std::{env, fmt, fs};
+
+pub use workload::Workload;
+
+/// The one client talking to the nodes.
+const CLIENT: &str = "c1";
+
+/// How long the client waits for any reply before giving up.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The node binary.
+    pub bin: PathBuf,
+    pub node_count: usize,
+    /// How long operations are issued for.
+    pub time_limit: Duration,
+    /// Operations per second.
+    pub rate: f64,
+    /// Delay of every message delivered to a node.
+    pub latency: Duration,
+    /// Random extra delay, up to this much, which also reorders messages.
+    pub jitter: Duration,
+    /// Probability of losing a message between nodes.
+    pub drop: f64,
+    /// Split the nodes in two halves every `nemesis_interval`, healing the
+    /// partition in between.
+    pub partition: bool,
+    /// Kill a random node every `nemesis_interval` and start it again, with
+    /// [`flyio::STATE_DIR`] set to a directory of the run. Needs processes,
+    /// simulated nodes cannot restart.
+    pub crash: bool,
+    pub nemesis_interval: Duration,
+    /// Time the nodes get to converge after the last operation, with the
+    /// network healed, before the final reads.
+    pub settle: Duration,
+    pub seed: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bin: PathBuf::new(),
+            node_count: 1,
+            time_limit: Duration::from_secs(10),
+            rate: 10.0,
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            drop: 0.0,
+            partition: false,
+            crash: false,
+            nemesis_interval: Duration::from_secs(5),
+            settle: Duration::from_secs(5),
+            seed: 0,
+        }
+    }
+}
+
+/// Counters Maelstrom reports as `net` stats.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct NetStats {
+    /// Messages between nodes, the basis of msgs-per-op.
+    pub server_messages: u64,
+    /// Messages between nodes lost to drops or partitions.
+    pub dropped: u64,
+    /// Messages between clients and nodes, both ways.
+    pub client_messages: u64,
+}
+
+/// The outcome of a run.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub seed: u64,
+    pub operations: u64,
+    pub ok: u64,
+    /// Operations answered with an error, by error code.
+    pub failed: HashMap<u32, u64>,
+    pub timed_out: u64,
+    pub net: NetStats,
+    pub latency_median: Duration,
+    pub latency_max: Duration,
+    pub history: History,
+    pub check: Check,
+}
+
+impl Report {
+    pub fn is_valid(&self) -> bool {
+        self.check.valid
+    }
+
+    pub fn msgs_per_op(&self) -> f64 {
+        self.net.server_messages as f64 / self.operations.max(1) as f64
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let failed: u64 = self.failed.values().sum();
+        writeln!(f, "seed:         {}", self.seed)?;
+        writeln!(
+            f,
+            "operations:   {} ({} ok, {failed} failed, {} timed out)",
+            self.operations, self.ok, self.timed_out
+        )?;
+        let mut codes: Vec<_> = self.failed.iter().collect();
+        codes.sort();
+        for (code, count) in codes {
+            writeln!(f, "  error {code}: {count}")?;
+        }
+        writeln!(
+            f,
+            "net:          {} server msgs ({} dropped), {} client msgs",
+            self.net.server_messages, self.net.dropped, self.net.client_messages
+        )?;
+        writeln!(f, "msgs-per-op:  {:.2}", self.msgs_per_op())?;
+        writeln!(
+            f,
+            "latency:      median {:?}, max {:?}",
+            self.latency_median, self.latency_max
+        )?;
+        for (property, count) in &self.check.violation_counts {
+            writeln!(f, "violations:   {count} {property}")?;
+        }
+        for violation in &self.check.violations {
+            writeln!(
+                f,
+                "  {}: {} (events {:?})",
+                violation.property, violation.description, violation.events
+            )?;
+        }
+        write!(f, "valid:        {}", self.is_valid())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Init,
+    Setup,
+    Operation,
+    Final,
+}
+
+struct Request {
+    node: String,
+    body: Value,
+    phase: Phase,
+    sent: Instant,
+}
+
+impl Request {
+    /// Whether it is part of the history, as opposed to `init` and setup.
+    fn is_operation(&self) -> bool {
+        matches!(self.phase, Phase::Operation | Phase::Final)
+    }
+}
+
+struct Harness<'a> {
+    config: Config,
+    rng: Rng,
+    cluster: &'a mut dyn Cluster,
+    workload: &'a mut dyn Workload,
+    node_ids: Vec<String>,
+    start: Instant,
+    next_msg_id: u64,
+    outstanding: HashMap<u64, Request>,
+    latencies: Vec<Duration>,
+    client_messages: u64,
+    report: Report,
+}
+
+fn node_ids(config: &Config) -> Vec<String> {
+    (1..=config.node_count).map(|i| format!("n{i}")).collect()
+}
+
+/// Runs `workload` against `config.node_count` copies of `config.bin`.
+pub fn run(config: Config, workload: &mut dyn Workload) -> Result<Report> {
+    // declared before the network so that it goes after the nodes are gone
+    let state_dir = if config.crash {
+        let name = format!("harness-{}-{}", std::process::id(), config.seed);
+        Some(StateDir::create(env::temp_dir().join(name))?)
+    } else {
+        None
+    };
+    let state_dir = state_dir.as_ref().map(|dir| dir.0.as_path());
+
+    let mut rng = Rng::new(config.seed);
+    let node_ids = node_ids(&config);
+    let mut network = Network::spawn(&config.bin, &node_ids, rng.next_u64(), state_dir)?;
+    network.set_latency(config.latency, config.jitter);
+    network.set_drop(config.drop);
+
+    let mut harness = Harness::new(config, rng, &mut network, workload);
+    let result = harness.run(true);
+    let report = result.map(|()| harness.finish());
+    network.shutdown()?;
+    report
+}
+
+/// The directory crashing nodes keep their state in, removed on every way
+/// out of [`run`].
+struct StateDir(PathBuf);
+
+impl StateDir {
+    fn create(path: PathBuf) -> Result<StateDir> {
+        fs::create_dir_all(&path).with_context(|| format!("creating {}", path.display()))?;
+        Ok(StateDir(path))
+    }
+}
+
+impl Drop for StateDir {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_dir_all(&self.0) {
+            eprintln!("[harness] removing {}: {err}", self.0.display());
+        }
+    }
+}
+
+/// Runs `workload` against a [`Simulation`] of `config.node_count` nodes
+/// built by `make_handler`, in virtual time.
+pub fn simulate<H, F>(
+    config: Config,
+    workload: &mut dyn Workload,
+    make_handler: F,
+) -> Result<Report>
+where
+    H: Handler,
+    F: FnMut(&Node) -> H,
+{
+    if config.crash {
+        bail!("simulated nodes cannot crash, run them with the harness and --bin instead");
+    }
+
+    let mut rng = Rng::new(config.seed);
+    let mut sim = Simulation::new(rng.next_u64(), config.node_count, make_handler)?;
+    sim.set_latency(config.latency, config.jitter);
+    sim.set_drop(config.drop);
+
+    let seed = config.seed;
+    let mut harness = Harness::new(config, rng, &mut sim, workload);
+    harness
+        .run(false)
+        .with_context(|| format!("simulation failed, replay it with --seed {seed}"))?;
+
+    Ok(harness.finish())
+}
+
+impl<'a> Harness<'a> {
+    fn new(
+        config: Config,
+        rng: Rng,
+        cluster: &'a mut dyn Cluster,
+        workload: &'a mut dyn Workload,
+    ) -> Self {
+        Harness {
+            rng,
+            start: cluster.now(),
+            cluster,
+            workload,
+            node_ids: node_ids(&config),
+            next_msg_id: 0,
+            outstanding: HashMap::new(),
+            latencies: Vec::new(),
+            client_messages: 0,
+            report: Report {
+                seed: config.seed,
+                ..Report::default()
+            },
+            config,
+        }
+    }
+
+    /// Drives the whole run, `init` included unless the cluster did it.
+    fn run(&mut self, init: bool) -> Result<()> {
+        if init {
+            for id in self.node_ids.clone() {
+                let body = json!({"type": "init", "node_id": id, "node_ids": self.node_ids});
+                self.request(&id, body, Phase::Init)?;
+            }
+            self.drain(Phase::Init)?;
+            if self.outstanding.values().any(|r| r.phase == Phase::Init) {
+                bail!("not every node answered `init`");
+            }
+        }
+
+        for (node, body) in self.workload.setup(&self.node_ids) {
+            self.request(&node, body, Phase::Setup)?;
+        }
+        self.drain(Phase::Setup)?;
+
+        let start = self.cluster.now();
+        let end = start + self.config.time_limit;
+        let interval = Duration::from_secs_f64(1.0 / self.config.rate.max(f64::MIN_POSITIVE));
+        let mut next_op = start;
+        let mut next_nemesis = start + self.config.nemesis_interval;
+
+        while next_op < end {
+            if self.cluster.now() >= next_op {
+                let (node, body) = self.workload.operation(&mut self.rng, &self.node_ids);
+                self.request(&node, body, Phase::Operation)?;
+                next_op += interval;
+            }
+
+            let nemesis = self.config.partition || self.config.crash;
+            if nemesis && self.cluster.now() >= next_nemesis {
+                self.nemesis()?;
+                next_nemesis += self.config.nemesis_interval;
+            }
+
+            let until = if nemesis {
+                next_op.min(next_nemesis)
+            } else {
+                next_op
+            };
+            self.step(until)?;
+        }
+
+        if self.cluster.is_partitioned() {
+            eprintln!("[harness] healing the network");
+            self.cluster.heal();
+        }
+        self.drain(Phase::Operation)?;
+
+        let settled = self.cluster.now() + self.config.settle;
+        while self.cluster.now() < settled {
+            self.step(settled)?;
+        }
+
+        for (node, body) in self.workload.final_operations(&self.node_ids) {
+            self.request(&node, body, Phase::Final)?;
+        }
+        self.drain(Phase::Final)
+    }
+
+    /// Does whatever nemeses are configured: partitions the nodes into two
+    /// random halves or heals the current partition, and restarts a random
+    /// node.
+    fn nemesis(&mut self) -> Result<()> {
+        if self.config.partition {
+            self.partition();
+        }
+        if self.config.crash {
+            self.crash()?;
+        }
+        Ok(())
+    }
+
+    fn partition(&mut self) {
+        if self.cluster.is_partitioned() {
+            eprintln!("[harness] healing the network");
+            self.cluster.heal();
+            return;
+        }
+
+        let mut nodes = self.node_ids.clone();
+        self.rng.shuffle(&mut nodes);
+        nodes.truncate(nodes.len() / 2);
+        eprintln!("[harness] partitioning {nodes:?} from the rest");
+        self.cluster
+            .partition(nodes.into_iter().collect::<HashSet<_>>());
+    }
+
+    /// Restarts a random node, which gets `init` again like Maelstrom would
+    /// send a new process.
+    fn crash(&mut self) -> Result<()> {
+        let Some(node) = self.rng.pick(&self.node_ids).cloned() else {
+            return Ok(());
+        };
+        eprintln!("[harness] restarting {node}");
+
+        let body = json!({"type": "init", "node_id": node, "node_ids": self.node_ids});
+        let line = self.track(&node, body, Phase::Init);
+        self.cluster.restart(&node, line)
+    }
+
+    fn request(&mut self, node: &str, body: Value, phase: Phase) -> Result<()> {
+        let line = self.track(node, body, phase);
+        self.cluster.send(node, line)
+    }
+
+    /// Gives `body` the next `msg_id` and waits for the reply to it, returns
+    /// the line to send.
+    fn track(&mut self, node: &str, mut body: Value, phase: Phase) -> String {
+        self.next_msg_id += 1;
+        body["msg_id"] = self.next_msg_id.into();
+
+        let line = json!({"src": CLIENT, "dest": node, "body": body}).to_string();
+        self.client_messages += 1;
+
+        let request = Request {
+            node: node.into(),
+            body,
+            phase,
+            sent: self.cluster.now(),
+        };
+        if request.is_operation() {
+            self.report.operations += 1;
+            self.record(
+                EventKind::Invoke,
+                self.next_msg_id,
+                &request,
+                request.body.clone(),
+            );
+        }
+        self.outstanding.insert(self.next_msg_id, request);
+        line
+    }
+
+    fn record(&mut self, kind: EventKind, op: u64, request: &Request, body: Value) {
+        let time = self.cluster.now() - self.start;
+        let is_final = request.phase == Phase::Final;
+        self.report
+            .history
+            .record(kind, op, &request.node, time, is_final, body);
+    }
+
+    /// Keeps the network going until every request of `phase` is answered
+    /// or timed out.
+    fn drain(&mut self, phase: Phase) -> Result<()> {
+        while self.outstanding.values().any(|r| r.phase == phase) {
+            self.step(self.cluster.now() + REQUEST_TIMEOUT)?;
+        }
+        Ok(())
+    }
+
+    /// Lets the cluster run until `until`, or less, handling what the nodes
+    /// sent to the client or to services meanwhile.
+    fn step(&mut self, until: Instant) -> Result<()> {
+        for line in self.cluster.step(until)? {
+            self.route(line)?;
+        }
+
+        self.expire(self.cluster.now());
+        Ok(())
+    }
+
+    fn route(&mut self, line: String) -> Result<()> {
+        let message: Value = serde_json::from_str(&line)
+            .with_context(|| format!("a node sent something that is not JSON: {line}"))?;
+        let (Some(node), Some(dest)) = (message["src"].as_str(), message["dest"].as_str()) else {
+            bail!("a node sent a message without `src` or `dest`: {line}");
+        };
+
+        if dest == CLIENT {
+            self.client_messages += 1;
+            self.reply(node, &message["body"]);
+        } else {
+            // there are no services here, answer like Maelstrom would for an
+            // unknown node
+            let reply = json!({
+                "src": dest,
+                "dest": node,
+                "body": {
+                    "type": "error",
+                    "in_reply_to": message["body"]["msg_id"],
+                    "code": u32::from(ErrorCode::NodeNotFound),
+                    "text": format!("no such node or service: {dest}"),
+                },
+            });
+            self.cluster.send(node, reply.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    fn reply(&mut self, node: &str, body: &Value) {
+        let Some((op, request)) = body["in_reply_to"]
+            .as_u64()
+            .and_then(|id| self.outstanding.remove_entry(&id))
+        else {
+            eprintln!("[harness] {node} replied to nothing: {body}");
+            return;
+        };
+
+        if !request.is_operation() {
+            if body["type"] == "error" {
+                eprintln!("[harness] {node} failed {}: {body}", request.body);
+            }
+            return;
+        }
+
+        self.latencies.push(self.cluster.now() - request.sent);
+        let kind = if body["type"] == "error" {
+            let code = body["code"].as_u64().unwrap_or_default() as u32;
+            *self.report.failed.entry(code).or_default() += 1;
+            EventKind::Fail
+        } else {
+            self.report.ok += 1;
+            EventKind::Ok
+        };
+        self.record(kind, op, &request, body.clone());
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let mut expired: Vec<u64> = self
+            .outstanding
+            .iter()
+            .filter(|(_, request)| now.duration_since(request.sent) >= REQUEST_TIMEOUT)
+            .map(|(op, _)| *op)
+            .collect();
+        // in order, for the history to replay exactly under simulation
+        expired.sort_unstable();
+
+        for op in expired {
+            let Some(request) = self.outstanding.remove(&op) else {
+                continue;
+            };
+            if request.is_operation() {
+                self.report.timed_out += 1;
+                self.record(EventKind::Info, op, &request, Value::Null);
+            } else {
+                eprintln!("[harness] {} never answered {}", request.node, request.body);
+            }
+        }
+    }
+
+    fn finish(mut self) -> Report {
+        self.latencies.sort();
+        if let Some(max) = self.latencies.last() {
+            self.report.latency_max = *max;
+            self.report.latency_median = self.latencies[self.latencies.len() / 2];
+        }
+
+        let traffic = self.cluster.traffic();
+        self.report.net = NetStats {
+            server_messages: traffic.messages,
+            dropped: traffic.dropped,
+            client_messages: self.client_messages,
+        };
+        self.report.check = checker::check(&self.report.history);
+        self.report
+    }
+}
@@ -0,0 +1,206 @@
+use crate::cluster::Cluster;
+use crate::process::{NodeProcess, Output};
+use anyhow::{bail, Context, Result};
+use flyio::sim::Traffic;
+use flyio::Rng;
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+/// How long the nodes get to exit once their stdin is closed.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize)]
+struct Route {
+    src: String,
+    dest: String,
+}
+
+/// Node binaries running as child processes, with their lines delivered to
+/// each other after a delay and some of them lost on the way when told to.
+pub(crate) struct Network {
+    bin: PathBuf,
+    state_dir: Option<PathBuf>,
+    nodes: HashMap<String, NodeProcess>,
+    send: mpsc::Sender<Output>,
+    output: Receiver<Output>,
+    rng: Rng,
+    queue: BinaryHeap<Reverse<(Instant, u64, String, String)>>,
+    seq: u64,
+    latency: Duration,
+    jitter: Duration,
+    drop: f64,
+    /// One side of the current partition, nodes on different sides cannot
+    /// talk to each other.
+    partition: Option<HashSet<String>>,
+    traffic: Traffic,
+}
+
+impl Network {
+    /// Starts a process of `bin` for every node, which keep their state in
+    /// `state_dir` if given.
+    pub(crate) fn spawn(
+        bin: &Path,
+        node_ids: &[String],
+        seed: u64,
+        state_dir: Option<&Path>,
+    ) -> Result<Self> {
+        let (send, output) = mpsc::channel();
+
+        let mut nodes = HashMap::new();
+        for id in node_ids {
+            let node = NodeProcess::spawn(bin, id, 0, state_dir, send.clone())?;
+            nodes.insert(id.clone(), node);
+        }
+
+        Ok(Network {
+            bin: bin.into(),
+            state_dir: state_dir.map(Into::into),
+            nodes,
+            send,
+            output,
+            rng: Rng::new(seed),
+            queue: BinaryHeap::new(),
+            seq: 0,
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            drop: 0.0,
+            partition: None,
+            traffic: Traffic::default(),
+        })
+    }
+
+    pub(crate) fn set_latency(&mut self, latency: Duration, jitter: Duration) {
+        self.latency = latency;
+        self.jitter = jitter;
+    }
+
+    pub(crate) fn set_drop(&mut self, drop: f64) {
+        self.drop = drop;
+    }
+
+    fn enqueue(&mut self, dest: &str, line: String) {
+        self.seq += 1;
+        let at = Instant::now() + self.latency + self.jitter.mul_f64(self.rng.unit());
+        self.queue.push(Reverse((at, self.seq, dest.into(), line)));
+    }
+
+    /// A message from one node to another, subject to drops and partitions.
+    fn send_between(&mut self, src: &str, dest: &str, line: String) {
+        self.traffic.messages += 1;
+
+        let split = self
+            .partition
+            .as_ref()
+            .is_some_and(|side| side.contains(src) != side.contains(dest));
+        if split || self.rng.chance(self.drop) {
+            self.traffic.dropped += 1;
+            return;
+        }
+
+        self.enqueue(dest, line);
+    }
+
+    fn deliver_due(&mut self, now: Instant) {
+        while let Some(Reverse((at, ..))) = self.queue.peek() {
+            if *at > now {
+                break;
+            }
+            let Some(Reverse((_, _, dest, line))) = self.queue.pop() else {
+                break;
+            };
+            if let Some(node) = self.nodes.get_mut(&dest) {
+                // a node that died just misses its messages, like over a network
+                if let Err(err) = node.write(&line) {
+                    eprintln!("[harness] writing to {dest}: {err:#}");
+                }
+            }
+        }
+    }
+}
+
+impl Cluster for Network {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn send(&mut self, dest: &str, line: String) -> Result<()> {
+        self.enqueue(dest, line);
+        Ok(())
+    }
+
+    fn step(&mut self, until: Instant) -> Result<Vec<String>> {
+        let mut outside = Vec::new();
+        let wake = self
+            .queue
+            .peek()
+            .map_or(until, |Reverse((due, ..))| (*due).min(until));
+
+        match self
+            .output
+            .recv_timeout(wake.saturating_duration_since(Instant::now()))
+        {
+            Ok(Output::Line { node, line }) => {
+                let route = serde_json::from_str::<Route>(&line).with_context(|| {
+                    format!("{node} printed something that is not a message: {line}")
+                })?;
+                if self.nodes.contains_key(&route.dest) {
+                    self.send_between(&route.src, &route.dest, line);
+                } else {
+                    outside.push(line);
+                }
+            }
+            Ok(Output::Exited { node, incarnation }) => {
+                // a process killed by a restart is expected to go away
+                if self.nodes.get(&node).map(|process| process.incarnation) == Some(incarnation) {
+                    bail!("{node} exited");
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => bail!("every node is gone"),
+        }
+
+        self.deliver_due(Instant::now());
+        Ok(outside)
+    }
+
+    fn partition(&mut self, side: HashSet<String>) {
+        self.partition = Some(side);
+    }
+
+    fn heal(&mut self) {
+        self.partition = None;
+    }
+
+    fn is_partitioned(&self) -> bool {
+        self.partition.is_some()
+    }
+
+    fn restart(&mut self, node: &str, init: String) -> Result<()> {
+        let old = self.nodes.remove(node).context("no such node")?;
+        let incarnation = old.incarnation + 1;
+        old.kill()?;
+
+        let state_dir = self.state_dir.as_deref();
+        let mut process =
+            NodeProcess::spawn(&self.bin, node, incarnation, state_dir, self.send.clone())?;
+        // ahead of anything on its way to the node, which has to start with it
+        process.write(&init)?;
+        self.nodes.insert(node.into(), process);
+        Ok(())
+    }
+
+    fn traffic(&self) -> Traffic {
+        self.traffic
+    }
+
+    fn shutdown(&mut self) -> Result<()> {
+        for (_, node) in self.nodes.drain() {
+            node.stop(SHUTDOWN_GRACE)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use anyhow::{Context, Result};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// What the node processes print, funneled into one channel.
+pub(crate) enum Output {
+    Line {
+        node: String,
+        line: String,
+    },
+    /// Stdout of the `incarnation`th process started for `node` is closed.
+    Exited {
+        node: String,
+        incarnation: u64,
+    },
+}
+
+/// A node binary running as a child process.
+pub(crate) struct NodeProcess {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    /// How many processes were started for the node before this one.
+    pub(crate) incarnation: u64,
+}
+
+impl NodeProcess {
+    /// Starts `bin`, forwarding every stdout line to `output` and every
+    /// stderr line to our stderr, prefixed with the node id. With
+    /// `state_dir` the node keeps its state there, see [`flyio::STATE_DIR`].
+    pub(crate) fn spawn(
+        bin: &Path,
+        id: &str,
+        incarnation: u64,
+        state_dir: Option<&Path>,
+        output: mpsc::Sender<Output>,
+    ) -> Result<Self> {
+        let mut command = Command::new(bin);
+        if let Some(dir) = state_dir {
+            command.env(flyio::STATE_DIR, dir);
+        }
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("starting {} for {id}", bin.display()))?;
+
+        let stdout = child.stdout.take().context("no stdout")?;
+        let node = id.to_string();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                let line = Output::Line {
+                    node: node.clone(),
+                    line,
+                };
+                if output.send(line).is_err() {
+                    return;
+                }
+            }
+            let _ = output.send(Output::Exited { node, incarnation });
+        });
+
+        let stderr = child.stderr.take().context("no stderr")?;
+        let node = id.to_string();
+        thread::spawn(move || {
+            for line in BufReader::new(stderr).lines() {
+                let Ok(line) = line else { break };
+                eprintln!("[{node}] {line}");
+            }
+        });
+
+        let stdin = child.stdin.take();
+        Ok(NodeProcess {
+            child,
+            stdin,
+            incarnation,
+        })
+    }
+
+    pub(crate) fn write(&mut self, line: &str) -> Result<()> {
+        let stdin = self.stdin.as_mut().context("stdin is closed")?;
+        stdin.write_all(line.as_bytes())?;
+        stdin.write_all(b"\n")?;
+        Ok(stdin.flush()?)
+    }
+
+    /// Closes stdin and gives the node `grace` to exit before killing it.
+    pub(crate) fn stop(mut self, grace: Duration) -> Result<()> {
+        drop(self.stdin.take());
+
+        let deadline = Instant::now() + grace;
+        while Instant::now() < deadline {
+            if self.child.try_wait()?.is_some() {
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        self.kill()
+    }
+
+    /// Kills the node outright, like a crash.
+    pub(crate) fn kill(mut self) -> Result<()> {
+        self.child.kill()?;
+        self.child.wait()?;
+        Ok(())
+    }
+}
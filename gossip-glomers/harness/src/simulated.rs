@@ -0,0 +1,47 @@
+use crate::cluster::Cluster;
+use anyhow::{bail, Result};
+use flyio::sim::{Simulation, Traffic};
+use flyio::Handler;
+use std::collections::HashSet;
+use std::time::Instant;
+
+impl<H> Cluster for Simulation<H>
+where
+    H: Handler,
+{
+    fn now(&self) -> Instant {
+        Simulation::now(self)
+    }
+
+    fn send(&mut self, dest: &str, line: String) -> Result<()> {
+        self.send_line(dest, line)
+    }
+
+    fn step(&mut self, until: Instant) -> Result<Vec<String>> {
+        self.run_until(until)
+    }
+
+    fn partition(&mut self, side: HashSet<String>) {
+        Simulation::partition(self, side);
+    }
+
+    fn heal(&mut self) {
+        Simulation::heal(self);
+    }
+
+    fn is_partitioned(&self) -> bool {
+        Simulation::is_partitioned(self)
+    }
+
+    fn restart(&mut self, node: &str, _init: String) -> Result<()> {
+        bail!("cannot restart {node}, simulated nodes have nowhere to keep their state")
+    }
+
+    fn traffic(&self) -> Traffic {
+        Simulation::traffic(self)
+    }
+
+    fn shutdown(&mut self) -> Result<()> {
+        Simulation::shutdown(self)
+    }
+}
@@ -0,0 +1,122 @@
+use anyhow::{bail, Result};
+use flyio::Rng;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// Client side of a Maelstrom workload: what to ask the nodes. The replies
+/// are recorded in a [`crate::history::History`] and verified by the
+/// [`crate::checker`].
+///
+/// Request bodies come without `msg_id`, the harness fills it in.
+pub trait Workload {
+    /// Requests sent to the nodes after `init`, before the first operation.
+    fn setup(&mut self, _node_ids: &[String]) -> Vec<(String, Value)> {
+        Vec::new()
+    }
+
+    /// The next operation and the node to send it to.
+    fn operation(&mut self, rng: &mut Rng, node_ids: &[String]) -> (String, Value);
+
+    /// Requests sent once the network healed and the nodes had time to
+    /// settle, e.g. a last read from every node.
+    fn final_operations(&mut self, _node_ids: &[String]) -> Vec<(String, Value)> {
+        Vec::new()
+    }
+}
+
+/// Builds a workload by its Maelstrom name.
+pub fn by_name(name: &str) -> Result<Box<dyn Workload>> {
+    Ok(match name {
+        "echo" => Box::new(Echo::default()),
+        "unique-ids" | "generate" => Box::new(Generate),
+        "broadcast" => Box::new(Broadcast::default()),
+        _ => bail!("unknown workload {name:?}, expected echo, unique-ids or broadcast"),
+    })
+}
+
+#[derive(Default)]
+pub struct Echo {
+    next: u64,
+}
+
+impl Workload for Echo {
+    fn operation(&mut self, rng: &mut Rng, node_ids: &[String]) -> (String, Value) {
+        self.next += 1;
+        let node = rng.pick(node_ids).cloned().unwrap_or_default();
+        let body = json!({"type": "echo", "echo": format!("Please echo {}", self.next)});
+        (node, body)
+    }
+}
+
+pub struct Generate;
+
+impl Workload for Generate {
+    fn operation(&mut self, rng: &mut Rng, node_ids: &[String]) -> (String, Value) {
+        let node = rng.pick(node_ids).cloned().unwrap_or_default();
+        (node, json!({"type": "generate"}))
+    }
+}
+
+#[derive(Default)]
+pub struct Broadcast {
+    next: i64,
+}
+
+/// Maelstrom's default `grid` topology: the nodes laid out row by row in a
+/// square, each connected to the ones above, below, left and right.
+pub fn grid(node_ids: &[String]) -> HashMap<String, Vec<String>> {
+    let width = (node_ids.len() as f64).sqrt().ceil().max(1.0) as usize;
+
+    node_ids
+        .iter()
+        .enumerate()
+        .map(|(i, id)| {
+            let mut neighbours = Vec::new();
+            if i >= width {
+                neighbours.push(node_ids[i - width].clone());
+            }
+            if i + width < node_ids.len() {
+                neighbours.push(node_ids[i + width].clone());
+            }
+            if i % width > 0 {
+                neighbours.push(node_ids[i - 1].clone());
+            }
+            if i % width + 1 < width && i + 1 < node_ids.len() {
+                neighbours.push(node_ids[i + 1].clone());
+            }
+            (id.clone(), neighbours)
+        })
+        .collect()
+}
+
+impl Workload for Broadcast {
+    fn setup(&mut self, node_ids: &[String]) -> Vec<(String, Value)> {
+        let topology = grid(node_ids);
+        node_ids
+            .iter()
+            .map(|id| {
+                (
+                    id.clone(),
+                    json!({"type": "topology", "topology": topology}),
+                )
+            })
+            .collect()
+    }
+
+    fn operation(&mut self, rng: &mut Rng, node_ids: &[String]) -> (String, Value) {
+        let node = rng.pick(node_ids).cloned().unwrap_or_default();
+        if rng.chance(0.5) {
+            return (node, json!({"type": "read"}));
+        }
+
+        self.next += 1;
+        (node, json!({"type": "broadcast", "message": self.next}))
+    }
+
+    fn final_operations(&mut self, node_ids: &[String]) -> Vec<(String, Value)> {
+        node_ids
+            .iter()
+            .map(|id| (id.clone(), json!({"type": "read"})))
+            .collect()
+    }
+}
@@ -0,0 +1,48 @@
+#!/usr/bin/env bash
+
+# Feeds the fixture histories to the checker: the clean ones must pass, the
+# others must be reported with their first violation and its operations.
+
+set -e
+
+cargo build --quiet --bin check
+failed=0
+
+valid() {
+  if ! ../target/debug/check "fixtures/$1.jsonl" > /dev/null; then
+    echo "FAIL fixtures/$1.jsonl should be valid"
+    failed=1
+  fi
+}
+
+# Checks the first violation reported: its property and the events of the
+# operations involved.
+invalid() {
+  local actual
+  if actual=$(../target/debug/check "fixtures/$1.jsonl" 2> /dev/null); then
+    echo "FAIL fixtures/$1.jsonl should not be valid"
+    failed=1
+    return
+  fi
+  actual=$(echo "$actual" | jq -c '.violations[0] | [.property, .events]')
+  if [ "$actual" != "[\"$1\",$2]" ]; then
+    echo "FAIL fixtures/$1.jsonl"
+    echo "  expected [\"$1\",$2]"
+    echo "  got      $actual"
+    failed=1
+  fi
+}
+
+valid counter
+invalid counter-out-of-bounds '[4,5]'
+invalid counter-divergence '[4,5,6,7]'
+
+valid kafka
+invalid kafka-duplicate-offset '[2,3,4,5]'
+invalid kafka-nonmonotonic-offset '[2,3,4,5]'
+invalid kafka-nonmonotonic-poll '[6,7]'
+invalid kafka-inconsistent-offset '[2,3,6,7]'
+invalid kafka-lost-write '[2,3,6,7]'
+invalid kafka-committed-regression '[8,9,10,11]'
+
+exit $failed
@@ -0,0 +1,7 @@
+#!/usr/bin/env bash
+
+set -e
+
+cargo build --bin kafka
+
+maelstrom test -w kafka --bin "../target/debug/kafka" --node-count 2 --concurrency 2n --time-limit 20 --rate 1000
@@ -0,0 +1,302 @@
+use anyhow::Result;
+use flyio::{Body, Error, Handler, Kv, Message, Node, Rpc, Timer};
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::mem;
+use std::time::Duration;
+
+const POLL: &str = "poll";
+
+type Offsets = HashMap<String, u64>;
+type Msgs = HashMap<String, Vec<(u64, u64)>>;
+
+/// A `send` handed over to the node owning the key.
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename = "send")]
+struct ForwardSend<'a> {
+    key: &'a str,
+    msg: u64,
+}
+
+/// A `poll` for the keys owned by the destination node.
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename = "poll")]
+struct ForwardPoll {
+    offsets: Offsets,
+}
+
+#[derive(Deserialize, Debug)]
+struct ForwardSendOK {
+    offset: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct ForwardPollOK {
+    msgs: Msgs,
+}
+
+#[derive(Body, Debug)]
+#[body(replies = BodyOut)]
+enum BodyIn {
+    #[reply(offset: u64)]
+    Send {
+        key: String,
+        msg: u64,
+    },
+    #[reply(msgs: Msgs)]
+    Poll {
+        offsets: Offsets,
+    },
+    #[reply]
+    CommitOffsets {
+        offsets: Offsets,
+    },
+    #[reply(offsets: Offsets)]
+    ListCommittedOffsets {
+        keys: Vec<String>,
+    },
+    // replies to forwarded requests arriving after they timed out
+    SendOK(IgnoredAny),
+    PollOK(IgnoredAny),
+}
+
+/// A client request waiting for other nodes to answer their part of it.
+///
+/// Forwarded requests are polled rather than waited on: two nodes blocking on
+/// each other's forwards would otherwise stall until the RPC timeout.
+enum Waiting {
+    Send {
+        client: String,
+        in_reply_to: usize,
+        rpc: Rpc,
+    },
+    Poll {
+        client: String,
+        in_reply_to: usize,
+        msgs: Msgs,
+        calls: Vec<Rpc>,
+    },
+}
+
+struct Kafka {
+    kv: Kv,
+    /// `(offset, msg)` in offset order, only for the keys this node owns.
+    logs: HashMap<String, Vec<(u64, u64)>>,
+    waiting: Vec<Waiting>,
+}
+
+/// FNV-1a of a key: every node, whatever it was built with, has to agree on
+/// the owner. `DefaultHasher` makes no promise across builds.
+fn hash(key: &str) -> u64 {
+    key.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// The node storing the log of `key`.
+fn owner<'a>(node: &'a Node, key: &str) -> &'a str {
+    if node.node_ids.is_empty() {
+        return &node.id;
+    }
+
+    let index = hash(key) % node.node_ids.len() as u64;
+
+    &node.node_ids[index as usize]
+}
+
+impl Kafka {
+    /// Takes the next offset of `key` from `lin-kv`, so offsets keep growing
+    /// even if the key moves to another node.
+    fn append(&mut self, node: &mut Node, key: String, msg: u64) -> Result<u64> {
+        let counter = format!("offset/{key}");
+        let offset = loop {
+            let next = self.kv.read::<u64>(node, &counter)?.unwrap_or(0);
+            if self.kv.cas(node, &counter, next, next + 1, true)? {
+                break next;
+            }
+        };
+
+        self.logs.entry(key).or_default().push((offset, msg));
+        Ok(offset)
+    }
+
+    fn read_log(&self, key: &str, from: u64) -> Option<Vec<(u64, u64)>> {
+        let log = self.logs.get(key)?;
+        let start = log.partition_point(|&(offset, _)| offset < from);
+        (start < log.len()).then(|| log[start..].to_vec())
+    }
+
+    fn commit(&self, node: &mut Node, offsets: &Offsets) -> Result<()> {
+        for (key, &offset) in offsets {
+            let committed = format!("committed/{key}");
+            loop {
+                let current = self.kv.read::<u64>(node, &committed)?;
+                if current.is_some_and(|current| current >= offset) {
+                    break;
+                }
+                if self
+                    .kv
+                    .cas(node, &committed, current.unwrap_or(0), offset, true)?
+                {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn list_committed(&self, node: &mut Node, keys: &[String]) -> Result<Offsets> {
+        let mut offsets = Offsets::new();
+        for key in keys {
+            if let Some(offset) = self.kv.read(node, &format!("committed/{key}"))? {
+                offsets.insert(key.clone(), offset);
+            }
+        }
+
+        Ok(offsets)
+    }
+
+    fn poll_waiting(&mut self, node: &mut Node) -> Result<()> {
+        for waiting in mem::take(&mut self.waiting) {
+            match waiting {
+                Waiting::Send {
+                    client,
+                    in_reply_to,
+                    mut rpc,
+                } => match rpc.try_wait::<ForwardSendOK>() {
+                    None => self.waiting.push(Waiting::Send {
+                        client,
+                        in_reply_to,
+                        rpc,
+                    }),
+                    Some(Ok(reply)) => {
+                        let outgoing = BodyOut::send_ok(reply.body.offset);
+                        node.reply_ok(&client, in_reply_to, outgoing)?;
+                    }
+                    Some(Err(err)) => node.reply_error(&client, in_reply_to, &Error::from(&err))?,
+                },
+                Waiting::Poll {
+                    client,
+                    in_reply_to,
+                    mut msgs,
+                    calls,
+                } => {
+                    let mut pending = Vec::with_capacity(calls.len());
+                    let mut failed = None;
+                    for mut rpc in calls {
+                        match rpc.try_wait::<ForwardPollOK>() {
+                            None => pending.push(rpc),
+                            Some(Ok(reply)) => msgs.extend(reply.body.msgs),
+                            Some(Err(err)) => failed = Some(Error::from(&err)),
+                        }
+                    }
+
+                    if let Some(error) = failed {
+                        node.reply_error(&client, in_reply_to, &error)?;
+                        continue;
+                    }
+
+                    if !pending.is_empty() {
+                        self.waiting.push(Waiting::Poll {
+                            client,
+                            in_reply_to,
+                            msgs,
+                            calls: pending,
+                        });
+                        continue;
+                    }
+
+                    node.reply_ok(&client, in_reply_to, BodyOut::poll_ok(msgs))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Handler for Kafka {
+    type Body = BodyIn;
+
+    fn start(&mut self, node: &mut Node) -> Result<()> {
+        let every = Timer::every(Duration::from_millis(10)).with_jitter(Duration::from_millis(1));
+        node.set_timer(POLL, every);
+        Ok(())
+    }
+
+    fn handle(&mut self, node: &mut Node, message: Message<BodyIn>) -> Result<()> {
+        match &message.body {
+            BodyIn::Send { key, msg } => {
+                let owner = owner(node, key).to_string();
+                if owner != node.id {
+                    let forward = ForwardSend { key, msg: *msg };
+                    self.waiting.push(Waiting::Send {
+                        client: message.src.clone(),
+                        in_reply_to: message.request_id()?,
+                        rpc: node.rpc(&owner, forward)?,
+                    });
+                    return Ok(());
+                }
+
+                let offset = self.append(node, key.clone(), *msg)?;
+                node.reply(&message, BodyOut::send_ok(offset))?;
+            }
+            BodyIn::Poll { offsets } => {
+                let mut msgs = Msgs::new();
+                let mut remote = HashMap::<String, Offsets>::new();
+                for (key, &offset) in offsets {
+                    let owner = owner(node, key);
+                    if owner != node.id {
+                        remote
+                            .entry(owner.into())
+                            .or_default()
+                            .insert(key.clone(), offset);
+                    } else if let Some(log) = self.read_log(key, offset) {
+                        msgs.insert(key.clone(), log);
+                    }
+                }
+
+                if remote.is_empty() {
+                    node.reply(&message, BodyOut::poll_ok(msgs))?;
+                    return Ok(());
+                }
+
+                let calls = remote
+                    .into_iter()
+                    .map(|(owner, offsets)| node.rpc(&owner, ForwardPoll { offsets }))
+                    .collect::<Result<_>>()?;
+                self.waiting.push(Waiting::Poll {
+                    client: message.src.clone(),
+                    in_reply_to: message.request_id()?,
+                    msgs,
+                    calls,
+                });
+            }
+            BodyIn::CommitOffsets { offsets } => {
+                self.commit(node, offsets)?;
+                node.reply(&message, BodyOut::commit_offsets_ok())?;
+            }
+            BodyIn::ListCommittedOffsets { keys } => {
+                let offsets = self.list_committed(node, keys)?;
+                node.reply(&message, BodyOut::list_committed_offsets_ok(offsets))?;
+            }
+            BodyIn::SendOK(_) | BodyIn::PollOK(_) => {}
+        }
+
+        self.poll_waiting(node)
+    }
+
+    fn timer(&mut self, node: &mut Node, _name: &str) -> Result<()> {
+        self.poll_waiting(node)
+    }
+}
+
+pub fn main() -> Result<()> {
+    Node::run(|_| Kafka {
+        kv: Kv::lin(),
+        logs: HashMap::new(),
+        waiting: Vec::new(),
+    })
+}
@@ -0,0 +1,23 @@
+#!/usr/bin/env bash
+
+set -e
+
+cargo build --bin kafka
+
+# n1 owns "a" and appends to it through lin-kv, "b" belongs to n2 so that
+# send and the poll for it are forwarded
+{
+  echo '{"src":"c1", "dest": "n1", "body":{"type":"init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1","n2"]}}'
+  echo '{"src":"c1", "dest": "n1", "body":{"type": "send", "msg_id": 2, "key": "a", "msg": 100}}'
+  sleep 0.1
+  echo '{"src":"lin-kv", "dest": "n1", "body":{"type": "error", "in_reply_to": 1, "code": 20, "text": "key does not exist"}}'
+  sleep 0.1
+  echo '{"src":"lin-kv", "dest": "n1", "body":{"type": "cas_ok", "in_reply_to": 2}}'
+  echo '{"src":"c1", "dest": "n1", "body":{"type": "send", "msg_id": 3, "key": "b", "msg": 200}}'
+  sleep 0.1
+  echo '{"src":"n2", "dest": "n1", "body":{"type": "send_ok", "in_reply_to": 4, "offset": 0}}'
+  echo '{"src":"c1", "dest": "n1", "body":{"type": "poll", "msg_id": 4, "offsets": {"a": 0, "b": 0}}}'
+  sleep 0.1
+  echo '{"src":"n2", "dest": "n1", "body":{"type": "poll_ok", "in_reply_to": 5, "msgs": {"b": [[0, 200]]}}}'
+  sleep 0.1
+} | ../target/debug/kafka
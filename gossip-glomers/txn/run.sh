@@ -0,0 +1,10 @@
+#!/usr/bin/env bash
+
+set -e
+
+cargo build --bin txn
+
+# the node reads ISOLATION too, so it applies and replicates writes accordingly
+export ISOLATION="${ISOLATION:-read-committed}"
+
+maelstrom test -w txn-rw-register --bin "../target/debug/txn" --node-count 2 --concurrency 2n --time-limit 20 --rate 1000 --consistency-models "$ISOLATION" --availability total --nemesis partition
@@ -0,0 +1,215 @@
+use anyhow::{bail, Result};
+use flyio::{Body, Error, ErrorCode, Handler, Message, Node, Retrier, Timer};
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::mem;
+use std::str::FromStr;
+use std::time::Duration;
+
+const REPLICATE: &str = "replicate";
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    #[serde(rename = "r")]
+    Read,
+    #[serde(rename = "w")]
+    Write,
+}
+
+/// A micro-operation: `["r", key, null]` or `["w", key, value]`, reads come
+/// back with the value filled in.
+#[derive(Deserialize, Serialize, Debug)]
+struct Op(Kind, u64, Option<u64>);
+
+/// Lamport timestamp of the transaction that wrote a value, the node id
+/// breaks ties so that every node orders concurrent writes the same way.
+type Version = (u64, String);
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(tag = "type", rename = "replicate")]
+struct Replicate {
+    version: Version,
+    writes: Vec<(u64, u64)>,
+}
+
+#[derive(Body, Debug)]
+#[body(replies = BodyOut)]
+enum BodyIn {
+    #[reply(txn: Vec<Op>)]
+    Txn {
+        txn: Vec<Op>,
+    },
+    #[reply]
+    Replicate {
+        #[body(flatten)]
+        replicate: Replicate,
+    },
+    // late acks of a replicate sent again since, the retrier only waits on
+    // the msg_id of its latest copy
+    ReplicateOK(IgnoredAny),
+}
+
+/// When the writes of a transaction take effect.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Isolation {
+    /// Every write is applied as it happens and replicated, intermediate
+    /// ones included.
+    ReadUncommitted,
+    /// Writes are buffered until the transaction commits, then only the last
+    /// one to each key is applied and replicated.
+    ReadCommitted,
+}
+
+impl Isolation {
+    const ENV: &'static str = "ISOLATION";
+
+    fn from_env() -> Result<Isolation> {
+        match std::env::var(Self::ENV) {
+            Ok(spec) => spec.parse(),
+            Err(std::env::VarError::NotPresent) => Ok(Isolation::ReadCommitted),
+            Err(err) => bail!("{}: {err}", Self::ENV),
+        }
+    }
+}
+
+impl FromStr for Isolation {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        Ok(match spec {
+            "read-uncommitted" => Isolation::ReadUncommitted,
+            "read-committed" => Isolation::ReadCommitted,
+            _ => bail!("unknown isolation {spec:?}, expected read-uncommitted or read-committed"),
+        })
+    }
+}
+
+struct Store {
+    isolation: Isolation,
+    peers: Vec<String>,
+    data: HashMap<u64, (u64, Version)>,
+    clock: u64,
+    replicate: Retrier<Replicate>,
+}
+
+impl Store {
+    /// Last writer wins, writes of one transaction share the version so the
+    /// later ones overwrite the earlier ones.
+    fn apply(&mut self, key: u64, value: u64, version: &Version) {
+        match self.data.get_mut(&key) {
+            Some((_, current)) if *current > *version => {}
+            Some(entry) => *entry = (value, version.clone()),
+            None => {
+                self.data.insert(key, (value, version.clone()));
+            }
+        }
+    }
+
+    fn execute(&mut self, node: &Node, txn: &mut [Op]) -> Result<Replicate> {
+        if let Some(Op(_, key, _)) = txn.iter().find(|op| op.0 == Kind::Write && op.2.is_none()) {
+            let text = format!("write to {key} without a value");
+            return Err(Error::new(ErrorCode::MalformedRequest, text).into());
+        }
+
+        self.clock += 1;
+        let version = (self.clock, node.id.clone());
+
+        let mut writes = Vec::new();
+        let mut buffered = HashMap::new();
+        for Op(kind, key, value) in txn.iter_mut() {
+            match (kind, self.isolation) {
+                (Kind::Read, Isolation::ReadUncommitted) => {
+                    *value = self.data.get(key).map(|(value, _)| *value);
+                }
+                (Kind::Read, Isolation::ReadCommitted) => {
+                    *value = match buffered.get(key) {
+                        Some(value) => Some(*value),
+                        None => self.data.get(key).map(|(value, _)| *value),
+                    };
+                }
+                (Kind::Write, Isolation::ReadUncommitted) => {
+                    let value = value.expect("checked above");
+                    self.apply(*key, value, &version);
+                    writes.push((*key, value));
+                }
+                (Kind::Write, Isolation::ReadCommitted) => {
+                    buffered.insert(*key, value.expect("checked above"));
+                }
+            }
+        }
+
+        // the commit
+        for (key, value) in buffered {
+            self.apply(key, value, &version);
+            writes.push((key, value));
+        }
+
+        Ok(Replicate { version, writes })
+    }
+}
+
+impl Handler for Store {
+    type Body = BodyIn;
+
+    fn start(&mut self, node: &mut Node) -> Result<()> {
+        let every = Timer::every(Duration::from_millis(100)).with_jitter(Duration::from_millis(10));
+        node.set_timer(REPLICATE, every);
+        Ok(())
+    }
+
+    fn handle(&mut self, node: &mut Node, mut message: Message<BodyIn>) -> Result<()> {
+        match &mut message.body {
+            BodyIn::Txn { txn } => {
+                let replicate = self.execute(node, txn)?;
+                let txn = mem::take(txn);
+                node.reply(&message, BodyOut::txn_ok(txn))?;
+
+                if replicate.writes.is_empty() {
+                    return Ok(());
+                }
+                for peer in &self.peers {
+                    let replicate = Replicate {
+                        version: replicate.version.clone(),
+                        writes: replicate.writes.clone(),
+                    };
+                    self.replicate.send(node, peer, replicate)?;
+                }
+            }
+            BodyIn::Replicate { replicate } => {
+                let Replicate { version, writes } = replicate;
+                self.clock = self.clock.max(version.0);
+                for &(key, value) in writes.iter() {
+                    self.apply(key, value, version);
+                }
+
+                node.reply(&message, BodyOut::replicate_ok())?;
+            }
+            BodyIn::ReplicateOK(_) => {}
+        }
+
+        Ok(())
+    }
+
+    fn timer(&mut self, node: &mut Node, _name: &str) -> Result<()> {
+        self.replicate.poll(node)?;
+        Ok(())
+    }
+}
+
+pub fn main() -> Result<()> {
+    let isolation = Isolation::from_env()?;
+
+    Node::run(|node| Store {
+        isolation,
+        peers: node
+            .node_ids
+            .iter()
+            .filter(|id| **id != node.id)
+            .cloned()
+            .collect(),
+        data: HashMap::new(),
+        clock: 0,
+        replicate: Retrier::new(Duration::from_millis(500), Duration::from_secs(4)),
+    })
+}
@@ -0,0 +1,16 @@
+#!/usr/bin/env bash
+
+set -e
+
+cargo build --bin txn
+
+for isolation in read-uncommitted read-committed; do
+{
+  echo '{"src":"c1", "dest": "n1", "body":{"type":"init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1","n2"]}}'
+  echo '{"src":"c1", "dest": "n1", "body":{"type": "txn", "msg_id": 2, "txn": [["r", 1, null], ["w", 1, 6], ["w", 1, 7], ["r", 1, null]]}}'
+  echo '{"src":"n2", "dest": "n1", "body":{"type": "replicate", "msg_id": 1, "version": [5, "n2"], "writes": [[2, 9]]}}'
+  echo '{"src":"c1", "dest": "n1", "body":{"type": "txn", "msg_id": 3, "txn": [["r", 1, null], ["r", 2, null]]}}'
+  echo '{"src":"c1", "dest": "n1", "body":{"type": "txn", "msg_id": 4, "txn": [["w", 3, 1], ["w", 3, null]]}}'
+  sleep 0.2
+} | ISOLATION="$isolation" ../target/debug/txn
+done
@@ -0,0 +1,12 @@
+#!/usr/bin/env bash
+
+# Restarts a node every second while partitioning the network, no id may come
+# up twice. Pick the format with ID_FORMAT.
+
+set -e
+
+cargo build --bin unique_ids
+cargo build -p harness --bin harness
+
+../target/debug/harness --bin ../target/debug/unique_ids --workload unique-ids --node-count 3 \
+  --time-limit 10 --rate 500 --nemesis crash --nemesis partition --nemesis-interval 1 "$@"
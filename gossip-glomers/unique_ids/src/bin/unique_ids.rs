@@ -1,68 +1,54 @@
-use anyhow::{bail, Context, Result};
-use flyio::{parse_message, send_message, take_init};
-use serde::{Deserialize, Serialize};
-use std::fmt::Write;
-use std::io::{self};
-
-#[derive(Deserialize, Serialize, Debug)]
-struct Generate {
-    msg_id: usize,
-}
-
-#[derive(Deserialize, Serialize, Debug)]
-struct GenerateOK<'a> {
-    msg_id: usize,
-    in_reply_to: usize,
-    id: &'a str,
+use anyhow::{Context, Result};
+use flyio::{Body, Handler, Id, IdFormat, IdGenerator, Message, Node};
+use serde_json::Value;
+
+#[derive(Body, Debug)]
+#[body(replies = BodyOut)]
+enum BodyIn {
+    #[reply(id: Id)]
+    Generate,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-#[serde(tag = "type")]
-enum Body<'a> {
-    #[serde(rename = "generate")]
-    Generate(Generate),
-    #[serde(borrow, rename = "generate_ok")]
-    GenerateOK(GenerateOK<'a>),
+struct UniqueIds {
+    ids: IdGenerator,
 }
 
-pub fn main() -> Result<()> {
-    let mut lines = io::stdin().lines();
-    let mut stdout = io::stdout().lock();
-
-    let node = take_init(&mut lines, &mut stdout)?;
-
-    let mut message_id: usize = 0;
-    let mut next_message_id = move || {
-        message_id += 1;
-        message_id
-    };
-
-    let mut id = String::with_capacity(100);
-
-    for line in lines {
-        let line = line.context("reading message")?;
-        let message = parse_message::<Body>(&line)?;
+impl Handler for UniqueIds {
+    type Body = BodyIn;
 
+    fn handle(&mut self, node: &mut Node, message: Message<BodyIn>) -> Result<()> {
         match message.body {
-            Body::Generate(body) => {
-                let msg_id = next_message_id();
+            BodyIn::Generate => {
+                let id = self.ids.next(node)?;
+                node.reply(&message, BodyOut::generate_ok(id))
+            }
+        }
+    }
 
-                id.clear();
-                write!(id, "{}-{}", &node.id, msg_id)?;
+    fn snapshot(&self) -> Result<Option<Value>> {
+        let reservation = serde_json::to_value(self.ids.reservation())?;
+        Ok(Some(reservation))
+    }
 
-                let outgoing = Body::GenerateOK(GenerateOK {
-                    msg_id,
-                    in_reply_to: body.msg_id,
-                    id: &id,
-                });
+    fn restore(&mut self, _node: &mut Node, snapshot: Value) -> Result<()> {
+        let reservation = serde_json::from_value(snapshot).context("restoring snapshot")?;
+        self.ids.recover(reservation);
+        Ok(())
+    }
 
-                send_message(&mut stdout, &node.id, message.src, outgoing)?;
-            }
-            Body::GenerateOK(_) => {
-                bail!("unexpected generate_ok message")
-            }
-        }
+    fn replay(&mut self, _node: &mut Node, record: Value) -> Result<()> {
+        let reservation = serde_json::from_value(record).context("replaying log")?;
+        self.ids.recover(reservation);
+        Ok(())
     }
+}
+
+pub fn main() -> Result<()> {
+    let format = IdFormat::from_env()?;
 
-    Ok(())
+    Node::try_run(|node| {
+        Ok(UniqueIds {
+            ids: IdGenerator::new(format, node)?,
+        })
+    })
 }